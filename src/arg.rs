@@ -1,7 +1,12 @@
+use chrono::{ DateTime, Utc };
 use clap::{Arg, Command};
+use std::sync::Arc;
+use crate::clock::{ AcceleratedClock, Clock, RealClock };
 use crate::recovery::repair;
+use crate::timezone::Zone;
 
-pub fn check_args() {
+// Returns the clock to run on, which replays time from --simulate-from at --speed when either is given
+pub fn check_args() -> Arc<dyn Clock> {
     let matches = Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -13,10 +18,42 @@ pub fn check_args() {
                 .help("Repair config file")
                 .action(clap::ArgAction::SetTrue)
         )
+        .arg(
+            Arg::new("simulate_from")
+                .long("simulate-from")
+                .value_name("DATETIME")
+                .help("Start the clock at the given date and time (e.g. \"2024-12-31 23:59:00\")")
+                .value_parser(parse_datetime)
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_name("MULTIPLIER")
+                .help("Run the clock faster than real time (e.g. 60 or 3600)")
+                .value_parser(parse_speed)
+        )
         .get_matches();
 
     if matches.get_flag("repair") {
         repair();
     }
+
+    let origin = matches.get_one::<DateTime<Utc>>("simulate_from").copied();
+    let speed = matches.get_one::<f64>("speed").copied();
+    if origin.is_none() && speed.is_none() {
+        return Arc::new(RealClock);
+    }
+    Arc::new(AcceleratedClock::new(Arc::new(RealClock), origin.unwrap_or_else(Utc::now), speed.unwrap_or(1.0)))
+}
+
+// RFC 3339, or a local date and time without offset
+fn parse_datetime(value: &str) -> Result<DateTime<Utc>, String> {
+    Zone::Local.parse_datetime(value).ok_or(format!("Invalid date and time: {}", value))
 }
 
+fn parse_speed(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("Speed must be a positive number: {}", value)),
+    }
+}
@@ -0,0 +1,53 @@
+use once_cell::sync::Lazy;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+
+use crate::log::{ print_flush, print_log, LogType };
+use crate::order;
+use crate::reload;
+
+// Sent by VRChat with the avatar ID whenever the avatar is changed or reloaded
+pub const AVATAR_CHANGE_ADDRESS: &str = "/avatar/change";
+
+static AVATAR: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn current() -> Option<String> {
+    AVATAR.lock().unwrap().clone()
+}
+
+// orders/avatar_<id>, if that avatar has its own orders
+pub fn orders_dir(avatar_id: &str) -> Option<PathBuf> {
+    order::orders_subdir("avatar", avatar_id)
+}
+
+// Switches to the orders of the new avatar, or back to the default ones
+pub fn change(avatar_id: &str) {
+    let previous = AVATAR.lock().unwrap().replace(avatar_id.to_string());
+    let old_dir = previous.as_deref().and_then(orders_dir);
+    let new_dir = orders_dir(avatar_id);
+
+    let folder = new_dir.as_deref().unwrap_or(Path::new("orders")).display().to_string();
+    print_flush(print_log(t!("avatar_changed", id = avatar_id, folder = folder), LogType::EVENT));
+
+    if old_dir != new_dir {
+        reload::reload_orders();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_ids_that_are_not_plain_names() {
+        assert_eq!(orders_dir(""), None);
+        assert_eq!(orders_dir("../orders"), None);
+        assert_eq!(orders_dir("avtr_1/../../x"), None);
+        assert_eq!(orders_dir("C:\\avtr"), None);
+    }
+
+    #[test]
+    fn ignores_avatars_without_a_folder() {
+        assert_eq!(orders_dir("avtr_00000000-0000-0000-0000-000000000000"), None);
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::{ Duration, SystemTime };
+use vrchat_osc::rosc::{ encoder, OscBundle, OscMessage, OscPacket, OscTime };
+
+use crate::config::Config;
+
+// Largest datagram that goes through without IP fragmentation on a typical network
+const MAX_PAYLOAD: usize = 1400;
+// "#bundle" and the timetag
+const BUNDLE_HEADER: usize = 16;
+// Size prefix of each element
+const ELEMENT_HEADER: usize = 4;
+// Special timetag meaning "apply as soon as received"
+const IMMEDIATELY: OscTime = OscTime { seconds: 0, fractional: 1 };
+
+// Packets for one batch of messages, bundled or not as configured
+pub fn packets(messages: Vec<OscMessage>, config: &Config) -> Vec<OscPacket> {
+    if !config.use_bundle {
+        return messages.into_iter().map(OscPacket::Message).collect();
+    }
+    pack(messages, timetag(config.bundle_delay_ms), MAX_PAYLOAD)
+}
+
+// The receiver compares timetags with its own wall clock, so they are never simulated
+fn timetag(delay_ms: u64) -> OscTime {
+    if delay_ms == 0 {
+        return IMMEDIATELY;
+    }
+    let at = SystemTime::now() + Duration::from_millis(delay_ms);
+    OscTime::try_from(at).unwrap_or(IMMEDIATELY)
+}
+
+// Addresses of the messages in a packet, bundled or not
+pub fn addresses(packet: &OscPacket) -> Vec<String> {
+    match packet {
+        OscPacket::Message(message) => vec![message.addr.clone()],
+        OscPacket::Bundle(bundle) => bundle.content.iter().flat_map(addresses).collect(),
+    }
+}
+
+// Packs messages into as few bundles as fit in `max_size` bytes each
+pub fn pack(messages: Vec<OscMessage>, timetag: OscTime, max_size: usize) -> Vec<OscPacket> {
+    let mut bundles: Vec<OscBundle> = Vec::new();
+    let mut size = 0;
+
+    for message in messages {
+        let packet = OscPacket::Message(message);
+        let length = encoder::encode(&packet).map(|bytes| bytes.len()).unwrap_or(0) + ELEMENT_HEADER;
+        match bundles.last_mut() {
+            // A message too large on its own still gets a bundle of its own
+            Some(bundle) if size + length <= max_size || bundle.content.is_empty() => {
+                bundle.content.push(packet);
+                size += length;
+            }
+            _ => {
+                bundles.push(OscBundle { timetag, content: vec![packet] });
+                size = BUNDLE_HEADER + length;
+            }
+        }
+    }
+    bundles.into_iter().map(OscPacket::Bundle).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vrchat_osc::rosc::OscType;
+
+    fn message(n: usize) -> OscMessage {
+        OscMessage {
+            addr: format!("/avatar/parameters/osc_clock@value_{:02}", n),
+            args: vec![OscType::Int(n as i32)],
+        }
+    }
+
+    fn encoded_len(packet: &OscPacket) -> usize {
+        encoder::encode(packet).unwrap().len()
+    }
+
+    #[test]
+    fn packs_one_tick_into_one_bundle() {
+        let messages: Vec<OscMessage> = (0..17).map(message).collect();
+        let packets = pack(messages.clone(), IMMEDIATELY, MAX_PAYLOAD);
+        assert_eq!(packets.len(), 1);
+        match &packets[0] {
+            OscPacket::Bundle(bundle) => {
+                assert_eq!(bundle.timetag, IMMEDIATELY);
+                assert_eq!(bundle.content, messages.into_iter().map(OscPacket::Message).collect::<Vec<_>>());
+            }
+            _ => panic!("expected a bundle"),
+        }
+    }
+
+    #[test]
+    fn splits_bundles_over_the_limit() {
+        let messages: Vec<OscMessage> = (0..100).map(message).collect();
+        let packets = pack(messages, IMMEDIATELY, 256);
+        assert!(packets.len() > 1);
+        let mut count = 0;
+        for packet in &packets {
+            assert!(encoded_len(packet) <= 256);
+            if let OscPacket::Bundle(bundle) = packet {
+                count += bundle.content.len();
+            }
+        }
+        assert_eq!(count, 100);
+    }
+
+    #[test]
+    fn lists_addresses_in_bundles() {
+        let packets = pack((0..3).map(message).collect(), IMMEDIATELY, MAX_PAYLOAD);
+        assert_eq!(
+            addresses(&packets[0]),
+            (0..3).map(|n| message(n).addr).collect::<Vec<_>>()
+        );
+        assert_eq!(addresses(&OscPacket::Message(message(5))), vec![message(5).addr]);
+    }
+
+    #[test]
+    fn oversized_message_gets_its_own_bundle() {
+        let packets = pack(vec![message(0), message(1)], IMMEDIATELY, 8);
+        assert_eq!(packets.len(), 2);
+    }
+
+    #[test]
+    fn unbundled_when_disabled() {
+        let config = Config::default();
+        let packets = packets(vec![message(0), message(1)], &config);
+        assert_eq!(packets, vec![OscPacket::Message(message(0)), OscPacket::Message(message(1))]);
+    }
+}
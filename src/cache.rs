@@ -0,0 +1,106 @@
+use chrono::{ DateTime, Duration, Utc };
+use std::collections::HashMap;
+use vrchat_osc::rosc::{ OscMessage, OscType };
+
+// Last value sent to each address, so values VRChat already has are not sent again
+pub struct ValueCache {
+    entries: HashMap<String, (Vec<OscType>, DateTime<Utc>)>,
+}
+
+impl ValueCache {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    // Sends a value when it changed, or when it has not been sent for `keepalive`
+    pub fn should_send(
+        &mut self,
+        message: &OscMessage,
+        keepalive: Option<Duration>,
+        now: DateTime<Utc>
+    ) -> bool {
+        if let Some((args, sent_at)) = self.entries.get(&message.addr) {
+            let due = match keepalive {
+                // The clock may have been moved back, in which case the value is resent too
+                Some(keepalive) => now - *sent_at >= keepalive || now < *sent_at,
+                None => false,
+            };
+            if *args == message.args && !due {
+                return false;
+            }
+        }
+        self.entries.insert(message.addr.clone(), (message.args.clone(), now));
+        true
+    }
+
+    // Forgets the values of addresses that could not be sent, so they are sent again
+    pub fn forget(&mut self, addresses: &[String]) {
+        for address in addresses {
+            self.entries.remove(address);
+        }
+    }
+
+    // Forgets addresses that are no longer sent, so they are sent fresh if added back
+    pub fn retain(&mut self, addresses: &[&str]) {
+        self.entries.retain(|address, _| addresses.contains(&address.as_str()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(value: i32) -> OscMessage {
+        OscMessage {
+            addr: "/avatar/parameters/osc_clock@minute_i".to_string(),
+            args: vec![OscType::Int(value)],
+        }
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn skips_unchanged_values() {
+        let mut cache = ValueCache::new();
+        assert!(cache.should_send(&message(1), None, at(0)));
+        assert!(!cache.should_send(&message(1), None, at(1)));
+        assert!(!cache.should_send(&message(1), None, at(3600)));
+        assert!(cache.should_send(&message(2), None, at(3601)));
+    }
+
+    #[test]
+    fn resends_after_keepalive() {
+        let mut cache = ValueCache::new();
+        let keepalive = Some(Duration::seconds(10));
+        assert!(cache.should_send(&message(1), keepalive, at(0)));
+        assert!(!cache.should_send(&message(1), keepalive, at(9)));
+        assert!(cache.should_send(&message(1), keepalive, at(10)));
+        assert!(!cache.should_send(&message(1), keepalive, at(15)));
+        // A change restarts the keepalive interval
+        assert!(cache.should_send(&message(2), keepalive, at(16)));
+        assert!(!cache.should_send(&message(2), keepalive, at(25)));
+        // Moving the clock back resends
+        assert!(cache.should_send(&message(2), keepalive, at(0)));
+    }
+
+    #[test]
+    fn forgets_removed_addresses() {
+        let mut cache = ValueCache::new();
+        assert!(cache.should_send(&message(1), None, at(0)));
+        cache.retain(&[]);
+        assert!(cache.should_send(&message(1), None, at(1)));
+    }
+
+    #[test]
+    fn resends_forgotten_values() {
+        let mut cache = ValueCache::new();
+        assert!(cache.should_send(&message(1), None, at(0)));
+        cache.forget(&[message(1).addr]);
+        assert!(cache.should_send(&message(1), None, at(1)));
+        assert!(!cache.should_send(&message(1), None, at(2)));
+    }
+}
@@ -0,0 +1,197 @@
+use chrono::{ DateTime, Duration, Utc };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex };
+
+// Source of "now" for everything that sends time values
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    // How many seconds of clock time pass per real second
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    fn is_real(&self) -> bool {
+        false
+    }
+
+    // Changes whenever time jumps, so ticks and schedules do not replay what was skipped
+    fn generation(&self) -> u64 {
+        0
+    }
+}
+
+// Wall clock
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn is_real(&self) -> bool {
+        true
+    }
+}
+
+// Stands still until it is moved with `set` or `advance`
+#[cfg(test)]
+pub struct FixedClock {
+    instant: Mutex<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl FixedClock {
+    pub fn new(instant: DateTime<Utc>) -> Self {
+        Self {
+            instant: Mutex::new(instant),
+        }
+    }
+
+    pub fn set(&self, instant: DateTime<Utc>) {
+        *self.instant.lock().unwrap() = instant;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut instant = self.instant.lock().unwrap();
+        *instant += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.instant.lock().unwrap()
+    }
+}
+
+// Another clock shifted by an amount that can be changed while running
+pub struct OffsetClock {
+    base: Arc<dyn Clock>,
+    offset: Mutex<Duration>,
+    // Bumped whenever the offset changes, as time jumps
+    generation: AtomicU64,
+}
+
+impl OffsetClock {
+    pub fn new(base: Arc<dyn Clock>, offset: Duration) -> Self {
+        Self {
+            base,
+            offset: Mutex::new(offset),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    // A zero offset removes the shift
+    pub fn set_offset(&self, offset: Duration) {
+        *self.offset.lock().unwrap() = offset;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Clock for OffsetClock {
+    // Stays on the base time if the shift would leave the range chrono can represent
+    fn now(&self) -> DateTime<Utc> {
+        let now = self.base.now();
+        now.checked_add_signed(*self.offset.lock().unwrap()).unwrap_or(now)
+    }
+
+    fn rate(&self) -> f64 {
+        self.base.rate()
+    }
+
+    fn is_real(&self) -> bool {
+        self.base.is_real() && self.offset.lock().unwrap().is_zero()
+    }
+
+    fn generation(&self) -> u64 {
+        self.base.generation() + self.generation.load(Ordering::SeqCst)
+    }
+}
+
+// Starts at `origin` and runs `multiplier` times faster than its base clock
+pub struct AcceleratedClock {
+    base: Arc<dyn Clock>,
+    base_start: DateTime<Utc>,
+    origin: DateTime<Utc>,
+    multiplier: f64,
+}
+
+impl AcceleratedClock {
+    pub fn new(base: Arc<dyn Clock>, origin: DateTime<Utc>, multiplier: f64) -> Self {
+        let base_start = base.now();
+        Self {
+            base,
+            base_start,
+            origin,
+            multiplier,
+        }
+    }
+}
+
+impl Clock for AcceleratedClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = self.base.now().signed_duration_since(self.base_start);
+        let scaled = (elapsed.num_microseconds().unwrap_or(i64::MAX) as f64) * self.multiplier;
+        self.origin + Duration::microseconds(scaled as i64)
+    }
+
+    fn rate(&self) -> f64 {
+        self.base.rate() * self.multiplier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn instant(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn fixed_clock_moves_only_when_told() {
+        let clock = FixedClock::new(instant("2024-02-28T23:59:59Z"));
+        assert_eq!(clock.now(), instant("2024-02-28T23:59:59Z"));
+        clock.advance(Duration::seconds(1));
+        assert_eq!(clock.now(), instant("2024-02-29T00:00:00Z"));
+        clock.set(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(clock.now(), instant("2025-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn offset_clock_follows_base() {
+        let base = Arc::new(FixedClock::new(instant("2024-12-31T23:59:30Z")));
+        let clock = OffsetClock::new(base.clone(), Duration::seconds(45));
+        assert_eq!(clock.now(), instant("2025-01-01T00:00:15Z"));
+        base.advance(Duration::seconds(15));
+        assert_eq!(clock.now(), instant("2025-01-01T00:00:30Z"));
+    }
+
+    #[test]
+    fn offset_changes_are_jumps() {
+        let base = Arc::new(FixedClock::new(instant("2024-12-31T23:59:30Z")));
+        let clock = OffsetClock::new(base, Duration::zero());
+        let generation = clock.generation();
+        clock.set_offset(Duration::hours(-1));
+        assert_eq!(clock.now(), instant("2024-12-31T22:59:30Z"));
+        assert_ne!(clock.generation(), generation);
+        // Out of range shifts keep the base time
+        clock.set_offset(Duration::max_value());
+        assert_eq!(clock.now(), instant("2024-12-31T23:59:30Z"));
+    }
+
+    #[test]
+    fn accelerated_clock_scales_elapsed_time() {
+        let base = Arc::new(FixedClock::new(instant("2000-01-01T00:00:00Z")));
+        let clock = AcceleratedClock::new(base.clone(), instant("2024-03-10T00:00:00Z"), 3600.0);
+        assert_eq!(clock.now(), instant("2024-03-10T00:00:00Z"));
+        base.advance(Duration::seconds(1));
+        assert_eq!(clock.now(), instant("2024-03-10T01:00:00Z"));
+        base.advance(Duration::milliseconds(500));
+        assert_eq!(clock.now(), instant("2024-03-10T01:30:00Z"));
+        assert_eq!(clock.rate(), 3600.0);
+        assert_eq!(OffsetClock::new(Arc::new(clock), Duration::hours(1)).rate(), 3600.0);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::log::print_flush;
 use crate::log::{print_log, LogType};
+use crate::schedule::ScheduleValue;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -13,17 +14,67 @@ use std::{fs, vec};
 pub struct Config {
     pub language: String,
     pub use_osc_query: bool,
+    pub osc_query_service_name: String,
+    pub osc_query_target: String,
     pub sender_ip: String,
     pub sender_port: u16,
     pub receiver_ip: String,
     pub receiver_port: u16,
     pub show_debug_log: bool,
-    pub send_all_value_every_time: bool,
-    pub check_rate_ms: u64,
-    pub restrict_send_rate: bool,
+    pub keepalive_seconds: u64,
+    pub tick_interval_ms: u64,
+    pub use_bundle: bool,
+    pub bundle_delay_ms: u64,
+    pub destinations: Vec<Destination>,
+    pub control_reply_ip: String,
+    pub control_reply_port: u16,
+    pub offline_parameters: Vec<OfflineParameter>,
     pub config_status: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Protocol {
+    #[default]
+    #[serde(rename = "udp")]
+    Udp,
+    #[serde(rename = "tcp")]
+    Tcp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Destination {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: Protocol,
+}
+
+// Sent once when osc_clock shuts down
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OfflineParameter {
+    pub address: String,
+    pub value: ScheduleValue,
+}
+
+impl Config {
+    // sender_ip and sender_port, followed by the extra destinations.
+    // A destination named "default" replaces the one from sender_ip and sender_port
+    pub fn destinations(&self) -> Vec<Destination> {
+        let mut destinations = Vec::new();
+        if !self.destinations.iter().any(|destination| destination.name == DEFAULT_DESTINATION) {
+            destinations.push(Destination {
+                name: DEFAULT_DESTINATION.to_string(),
+                host: self.sender_ip.clone(),
+                port: self.sender_port,
+                protocol: Protocol::Udp,
+            });
+        }
+        destinations.extend(self.destinations.iter().cloned());
+        destinations
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ConfigStatus {
     #[serde(rename = "Passed")]
@@ -43,19 +94,29 @@ impl Default for Config {
         Self {
             language: "en".to_string(),
             use_osc_query: false,
+            osc_query_service_name: "osc-clock".to_string(),
+            osc_query_target: "VRChat-Client-*".to_string(),
             sender_ip: "127.0.0.1".to_string(),
             sender_port: 9000,
             receiver_ip: "127.0.0.1".to_string(),
             receiver_port: 9001,
             show_debug_log: false,
-            send_all_value_every_time: false,
-            check_rate_ms: 1,
-            restrict_send_rate: true,
+            keepalive_seconds: 0,
+            tick_interval_ms: 1000,
+            use_bundle: false,
+            bundle_delay_ms: 0,
+            destinations: vec![],
+            control_reply_ip: "127.0.0.1".to_string(),
+            control_reply_port: 0,
+            offline_parameters: vec![],
             config_status: format!("{:?}", ConfigStatus::Fallback),
         }
     }
 }
 
+pub const MIN_TICK_INTERVAL_MS: u64 = 10;
+pub const DEFAULT_DESTINATION: &str = "default";
+
 pub static CONFIG: Lazy<Mutex<Config>> = Lazy::new(|| Mutex::new(load_config()));
 
 pub fn init_config() {
@@ -205,16 +266,6 @@ fn load_config() -> Config {
         Ok(result) => {
             config = result;
             rust_i18n::set_locale(&config.language);
-            if config.config_status == format!("{:?}", ConfigStatus::Lacked) {
-                print_flush(
-                    print_log(
-                        format!(
-                            "Deprecated or lacked properties config has been detected. Use \"osc_clock.exe --repair\" to fix the config file."
-                        ),
-                        LogType::WARN
-                    )
-                );
-            }
         }
         Err(_error) => {
             // Handle error when loading config file
@@ -253,38 +304,38 @@ fn load_config() -> Config {
         }
     }
 
-    if config.send_all_value_every_time {
+    check_config(&config);
+
+    if config.use_osc_query {
         print_flush(print_log(
-            t!("warning_send_all_value").to_string(),
-            LogType::WARN,
+            t!("warning_osc_query_enabled").to_string(),
+            LogType::INFO,
         ));
     }
 
-    if !config.restrict_send_rate {
-        print_flush(print_log(
-            t!("warning_restrict_send_rate").to_string(),
-            LogType::WARN,
-        ));
+    config
+}
+
+// Warnings for a config that was read, whether on start or on reload
+pub fn check_config(config: &Config) {
+    if config.config_status == format!("{:?}", ConfigStatus::Lacked) {
+        print_flush(
+            print_log(
+                "Deprecated or lacked properties config has been detected. Use \"osc_clock.exe --repair\" to fix the config file.".to_string(),
+                LogType::WARN
+            )
+        );
     }
 
-    if config.check_rate_ms == 0 {
+    if config.tick_interval_ms < MIN_TICK_INTERVAL_MS {
         print_flush(print_log(
-            t!("warning_check_rate_ms_zero").to_string(),
+            t!("warning_tick_interval_too_short", min = MIN_TICK_INTERVAL_MS).to_string(),
             LogType::WARN,
         ));
-    } else if config.check_rate_ms > 100 {
+    } else if !1000u64.is_multiple_of(config.tick_interval_ms) && !config.tick_interval_ms.is_multiple_of(1000) {
         print_flush(print_log(
-            t!("warning_check_rate_ms_too_much").to_string(),
+            t!("warning_tick_interval_unaligned").to_string(),
             LogType::WARN,
         ));
     }
-
-    if config.use_osc_query {
-        print_flush(print_log(
-            t!("warning_osc_query_enabled").to_string(),
-            LogType::INFO,
-        ));
-    }
-
-    return config;
 }
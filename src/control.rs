@@ -0,0 +1,192 @@
+use chrono::Duration;
+use once_cell::sync::Lazy;
+use std::path::PathBuf;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Mutex;
+use vrchat_osc::rosc::{ OscMessage, OscPacket, OscType };
+
+use crate::clock::OffsetClock;
+use crate::config::{ Destination, Protocol, CONFIG };
+use crate::log::{ print_flush, print_log, LogType };
+use crate::order;
+use crate::receiver::full_sync;
+use crate::reload;
+use crate::sender::send;
+use crate::services;
+
+// Commands are received at /osc_clock/<command>, and answered at /osc_clock/ack/<command>
+pub const CONTROL_PREFIX: &str = "/osc_clock/";
+const ACK_PREFIX: &str = "/osc_clock/ack/";
+// About 100 years either way
+const MAX_OFFSET_SECONDS: f64 = 100.0 * 365.25 * 86400.0;
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static PROFILE: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+// Whether the sender loop holds back its values
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+// Orders profile chosen with /osc_clock/profile
+pub fn profile() -> Option<String> {
+    PROFILE.lock().unwrap().clone()
+}
+
+// orders/profile_<name>, if it exists
+pub fn profile_dir(name: &str) -> Option<PathBuf> {
+    order::orders_subdir("profile", name)
+}
+
+pub fn handle(command: &str, args: &[OscType], clock: &OffsetClock) {
+    let value = args.first();
+    print_flush(
+        print_log(
+            t!("control_command", command = command, value = format!("{:?}", value)),
+            LogType::EVENT
+        )
+    );
+
+    let reply = match command {
+        // Buttons send true when pressed and false when released, so only the press counts
+        "pause" | "resume" | "sync" | "reload" | "services" if !pressed(value) => {
+            return;
+        }
+        "pause" | "resume" => {
+            let was_paused = PAUSED.swap(command == "pause", Ordering::SeqCst);
+            // Avatar changes and syncs are not sent while paused, so everything goes out on resume
+            if was_paused && !is_paused() {
+                full_sync(clock);
+            }
+            OscType::Bool(is_paused())
+        }
+        "sync" => OscType::Bool(full_sync(clock)),
+        "reload" => OscType::Bool(reload::reload_all()),
+        "services" => {
+            services::print_table(&CONFIG.lock().unwrap().osc_query_target);
+            let names: Vec<String> = services::list().into_iter().map(|service| service.name).collect();
+            OscType::String(names.join(","))
+        }
+        "offset_seconds" => {
+            match value.and_then(number) {
+                Some(seconds) => match offset(seconds) {
+                    Some(offset) => {
+                        clock.set_offset(offset);
+                        OscType::Float(seconds as f32)
+                    }
+                    None => {
+                        control_failed(command, "offset must be a finite number within 100 years");
+                        OscType::Bool(false)
+                    }
+                },
+                None => {
+                    control_failed(command, "expected a number");
+                    OscType::Bool(false)
+                }
+            }
+        }
+        "profile" => {
+            match value {
+                // Empty or "default" goes back to the avatar's or the default orders
+                Some(OscType::String(name)) if name.is_empty() || name == "default" => {
+                    *PROFILE.lock().unwrap() = None;
+                    reload::reload_orders();
+                }
+                Some(OscType::String(name)) if profile_dir(name).is_some() => {
+                    *PROFILE.lock().unwrap() = Some(name.clone());
+                    reload::reload_orders();
+                }
+                Some(OscType::String(name)) => control_failed(command, &format!("orders/profile_{} not found", name)),
+                _ => control_failed(command, "expected a profile name"),
+            }
+            OscType::String(profile().unwrap_or_default())
+        }
+        _ => {
+            print_flush(print_log(t!("unknown_control_command", command = command), LogType::WARN));
+            return;
+        }
+    };
+    acknowledge(command, reply);
+}
+
+fn pressed(value: Option<&OscType>) -> bool {
+    match value {
+        Some(OscType::Bool(b)) => *b,
+        Some(value) => number(value).is_none_or(|n| n != 0.0),
+        None => true,
+    }
+}
+
+fn number(value: &OscType) -> Option<f64> {
+    match value {
+        OscType::Int(i) => Some(*i as f64),
+        OscType::Long(l) => Some(*l as f64),
+        OscType::Float(f) => Some(*f as f64),
+        OscType::Double(d) => Some(*d),
+        _ => None,
+    }
+}
+
+// Rejects offsets that are not a number or would move the clock out of any useful range
+fn offset(seconds: f64) -> Option<Duration> {
+    if !seconds.is_finite() || seconds.abs() > MAX_OFFSET_SECONDS {
+        return None;
+    }
+    Some(Duration::milliseconds((seconds * 1000.0) as i64))
+}
+
+fn control_failed(command: &str, error: &str) {
+    print_flush(print_log(t!("control_failed", command = command, error = error), LogType::WARN));
+}
+
+// Reports the state after a command, if a reply port is configured
+fn acknowledge(command: &str, state: OscType) {
+    let config = CONFIG.lock().unwrap().clone();
+    if config.control_reply_port == 0 {
+        return;
+    }
+    let destination = Destination {
+        name: "control_reply".to_string(),
+        host: config.control_reply_ip,
+        port: config.control_reply_port,
+        protocol: Protocol::Udp,
+    };
+    let message = OscMessage {
+        addr: format!("{}{}", ACK_PREFIX, command),
+        args: vec![state],
+    };
+    tokio::spawn(async move {
+        let _ = send(OscPacket::Message(message), &destination).await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn button_presses() {
+        assert!(pressed(None));
+        assert!(pressed(Some(&OscType::Bool(true))));
+        assert!(!pressed(Some(&OscType::Bool(false))));
+        assert!(pressed(Some(&OscType::Int(1))));
+        assert!(!pressed(Some(&OscType::Int(0))));
+        assert!(!pressed(Some(&OscType::Float(0.0))));
+        assert!(pressed(Some(&OscType::String("go".to_string()))));
+    }
+
+    #[test]
+    fn offsets_within_range() {
+        assert_eq!(offset(1.5), Some(Duration::milliseconds(1500)));
+        assert_eq!(offset(-3600.0), Some(Duration::hours(-1)));
+        assert_eq!(offset(f64::NAN), None);
+        assert_eq!(offset(f64::INFINITY), None);
+        assert_eq!(offset(1e13), None);
+    }
+
+    #[test]
+    fn rejects_unsafe_profile_names() {
+        assert_eq!(profile_dir("../config"), None);
+        assert_eq!(profile_dir("no_such_profile"), None);
+    }
+}
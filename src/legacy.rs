@@ -1,24 +1,31 @@
-use vrchat_osc::rosc::OscMessage;
+use std::sync::Arc;
+use vrchat_osc::Error;
+use vrchat_osc::rosc::OscPacket;
 
-use crate::config::CONFIG;
+use crate::clock::OffsetClock;
+use crate::config::Destination;
 use crate::sender::{ sender, send };
 use crate::receiver::receiver;
+use crate::shutdown::{ self, Tasks };
 
-pub async fn start() {
-    let send = |m: OscMessage| {
-        let config = CONFIG.lock().unwrap().clone();
+pub async fn start(clock: Arc<OffsetClock>) -> Result<(), Error> {
+    let mut tasks = spawn(clock);
+    shutdown::signal().await?;
+    shutdown::stop(&mut tasks).await;
+    Ok(())
+}
+
+// Sends and receives on the configured ports, until the tasks are stopped
+pub fn spawn(clock: Arc<OffsetClock>) -> Tasks {
+    let send = |packet: OscPacket, destination: Destination| {
         async move {
-            send(m, &config.sender_ip, config.sender_port);
+            send(packet, &destination).await.is_ok()
         }
     };
 
-    let sender_task = tokio::spawn(async move {
-        sender(send).await;
-    });
-
-    let receiver_task = tokio::spawn(async move {
-        receiver().await;
-    });
-
-    let _ = tokio::join!(sender_task, receiver_task);
+    let mut tasks = Tasks::default();
+    let sender_clock = clock.clone();
+    tasks.spawn(|stopping| sender(send, stopping, sender_clock));
+    tasks.spawn(|stopping| receiver(stopping, clock));
+    tasks
 }
@@ -2,14 +2,32 @@
 extern crate rust_i18n;
 i18n!("locales");
 
+use std::sync::Arc;
+
+mod avatar;
+mod bundle;
+mod cache;
+mod clock;
 mod config;
+mod control;
 mod legacy;
 mod log;
 mod message;
 mod osc_query;
+mod pattern;
 mod receiver;
 mod recovery;
+mod reload;
+mod schedule;
 mod sender;
+mod services;
+mod shutdown;
+mod supervisor;
+mod ticker;
+mod transform;
+mod transport;
+mod trigger;
+mod timezone;
 mod order;
 mod unit;
 mod arg;
@@ -17,7 +35,7 @@ mod arg;
 #[tokio::main]
 async fn main() -> Result<(), vrchat_osc::Error> {
     // Check arguments
-    arg::check_args();
+    let clock = Arc::new(clock::OffsetClock::new(arg::check_args(), chrono::Duration::zero()));
 
     // Display Title
     print!("{} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
@@ -29,13 +47,19 @@ async fn main() -> Result<(), vrchat_osc::Error> {
     // Init and load orders
     order::init_orders();
 
+    // Load alarms and other scheduled triggers
+    schedule::init_schedules();
+
+    // Reload config and orders when their files change
+    tokio::spawn(reload::watch());
+
     // Choose the communication method based on the configuration
     if config::CONFIG.lock().unwrap().use_osc_query {
-        // Start with OSC Query
-        osc_query::start().await?;
+        // Start with OSC Query, falling back to normal OSC while VRChat is not found
+        supervisor::start(clock).await?;
     } else {
         // Start with normal OSC communication
-        legacy::start().await;
+        legacy::start(clock).await?;
     }
 
     Ok(())
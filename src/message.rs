@@ -1,46 +1,42 @@
 
-use chrono::Local;
+use chrono::{ DateTime, Local, Utc };
 use vrchat_osc::rosc::{ OscMessage, OscType };
-use serde::{Serialize, Deserialize};
 use crate::{ config::CONFIG, log::{ print_flush, print_log, LogType }, unit::handle_unit };
 use crate::order::{ Order};
+use crate::transform;
 
-use bitflags::bitflags;
-
-bitflags! {
-    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
-    pub struct SyncFlag: u32 {
-        const MINUTE = 0b00000001;
-        const HOUR   = 0b00000010;
-        const DAY    = 0b00000100;
-    }
+pub struct BuilderParams<'a> {
+    pub orders: &'a [Order],
+    // Instant to build the values for, read from the clock by the caller
+    pub now: DateTime<Utc>,
 }
 
-pub struct BuilderParams {
-    pub orders: Vec<Order>,
-    pub sync_flag: SyncFlag,
-}
-
-pub fn build(params: BuilderParams) -> Vec<OscMessage> {
-    let dt = Local::now();
+// Every value of the orders, with the order each one was built from
+pub fn build<'a>(params: BuilderParams<'a>) -> Vec<(&'a Order, OscMessage)> {
+    let dt = params.now;
 
     if CONFIG.lock().unwrap().show_debug_log {
         let str = t!(
             "debug_on_make_message",
-            timestamp = dt.format("%Y-%m-%d %H:%M:%S.%f"),
-            minute = format!("{:<5}", params.sync_flag.contains(SyncFlag::MINUTE)),
-            hour = format!("{:<5}", params.sync_flag.contains(SyncFlag::HOUR)),
-            date = format!("{:<5}", params.sync_flag.contains(SyncFlag::DAY))
+            timestamp = dt.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S.%f")
         );
         print_flush(print_log(str, LogType::INFO));
     }
 
-    let mut messages: Vec<OscMessage> = Vec::new();
+    let mut messages: Vec<(&Order, OscMessage)> = Vec::new();
     
     for order in params.orders.iter() {
-        let msg = make_message(&order.address, vec![handle_unit(order.r#type, dt, params.sync_flag)]);
+        let msg = make_message(
+            &order.address,
+            vec![
+                transform::apply(
+                    handle_unit(order, order.zone().convert(dt)),
+                    order.transforms.as_deref().unwrap_or_default()
+                )
+            ]
+        );
         if msg.args[0] != OscType::Nil {
-            messages.push(msg);
+            messages.push((order, msg));
         }
     }
     return messages;
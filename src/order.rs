@@ -1,60 +1,172 @@
+use crate::avatar;
+use crate::config::{ Destination, CONFIG };
+use crate::control;
 use crate::log::{print_flush, print_log, LogType};
-use crate::unit::UnitType;
+use crate::pattern::AddressPattern;
+use crate::timezone::Zone;
+use crate::transform::Transform;
+use crate::trigger::Trigger;
+use crate::unit::{ UnitParams, UnitType };
+use chrono::{ DateTime, Utc };
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use std::io::Write;
-use std::path::Path;
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
 use std::{fs, io};
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Order {
     pub r#type: UnitType,
     pub address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default, skip_serializing_if = "UnitParams::is_empty")]
+    pub params: UnitParams,
+    // Resends an unchanged value after this many seconds, overriding config.json
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive_seconds: Option<u64>,
+    // Names of the destinations to send to. All of them when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destinations: Option<Vec<String>>,
+    // When an update_handler fires. Any true or non-bool value when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<Trigger>,
+    // Ignores further triggers of an update_handler for this long after it fires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debounce_ms: Option<u64>,
+    // Applied in turn to the value of the unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<Vec<Transform>>,
+    // Address compiled as a pattern when the orders are loaded (handlers only)
+    #[serde(skip)]
+    pub pattern: Option<AddressPattern>,
+    // params.target and params.start, read when the orders are loaded
+    #[serde(skip)]
+    target_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    start_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl Order {
+    pub fn new(r#type: UnitType, address: &str) -> Self {
+        Self {
+            r#type,
+            address: address.to_string(),
+            timezone: None,
+            params: UnitParams::default(),
+            keepalive_seconds: None,
+            destinations: None,
+            trigger: None,
+            debounce_ms: None,
+            transforms: None,
+            pattern: None,
+            target_at: None,
+            start_at: None,
+        }
+    }
+
+    // Orders without a (valid) time zone are evaluated in local time
+    pub fn zone(&self) -> Zone {
+        self.timezone.as_deref().and_then(Zone::parse).unwrap_or(Zone::Local)
+    }
+
+    // Reads params.target and params.start in the order's time zone, so ticks do not parse them again
+    pub fn parse_times(&mut self) {
+        let zone = self.zone();
+        self.target_at = self.params.target.as_deref().and_then(|target| zone.parse_datetime(target));
+        self.start_at = self.params.start.as_deref().and_then(|start| zone.parse_datetime(start));
+    }
+
+    pub fn target(&self) -> Option<DateTime<Utc>> {
+        self.target_at
+    }
+
+    pub fn start(&self) -> Option<DateTime<Utc>> {
+        self.start_at
+    }
+
+    // Whether a received address is what this order listens to
+    pub fn matches(&self, address: &str) -> bool {
+        match &self.pattern {
+            Some(pattern) => pattern.matches(address),
+            None => self.address == address,
+        }
+    }
+
+    pub fn sends_to(&self, destination: &str) -> bool {
+        match &self.destinations {
+            Some(names) => names.iter().any(|name| name == destination),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Orders {
     pub sender: Vec<Order>,
     pub handler: Vec<Order>,
 }
 
-pub static ORDERS: Lazy<Orders> = Lazy::new(|| load_orders());
+pub static ORDERS: Lazy<Mutex<Orders>> = Lazy::new(|| Mutex::new(load_orders()));
 
 pub fn init_orders() {
     Lazy::force(&ORDERS);
 }
 
-pub fn load_orders() -> Orders {
-    let mut orders = Vec::new();
-    let orders_dir = Path::new("orders");
-
-    if !orders_dir.exists() {
-        if let Err(e) = fs::create_dir_all(orders_dir) {
-            eprintln!("Failed to create orders directory: {}", e);
-            return split(get_fallback_orders());
-        }
-    }
+// Orders read from a directory, and the files that could not be read
+struct OrderFiles {
+    orders: Vec<Order>,
+    errors: Vec<String>,
+    found_file: bool,
+}
 
-    let mut found_file = false;
+fn read_orders_dir(orders_dir: &Path) -> OrderFiles {
+    let mut files = OrderFiles {
+        orders: Vec::new(),
+        errors: Vec::new(),
+        found_file: false,
+    };
 
     if let Ok(entries) = fs::read_dir(orders_dir) {
         for entry in entries.flatten() {
             let path = entry.path();
             if let Some(fname) = path.file_name().and_then(|n| n.to_str()) {
                 if fname.starts_with("orders_") && fname.ends_with(".json") {
-                    found_file = true;
-                    if let Ok(data) = fs::read_to_string(&path) {
-                        if let Ok(mut file_orders) = serde_json::from_str::<Vec<Order>>(&data) {
-                            orders.append(&mut file_orders);
-                        }
+                    files.found_file = true;
+                    let file_orders = fs::read_to_string(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|data| serde_json::from_str::<Vec<Order>>(&data).map_err(|e| e.to_string()));
+                    match file_orders {
+                        Ok(mut file_orders) => files.orders.append(&mut file_orders),
+                        Err(error) => files.errors.push(format!("{}: {}", path.display(), error)),
                     }
                 }
             }
         }
     }
+    files
+}
+
+pub fn load_orders() -> Orders {
+    let mut orders;
+    let orders_dir = Path::new("orders");
+
+    if !orders_dir.exists() {
+        if let Err(e) = fs::create_dir_all(orders_dir) {
+            eprintln!("Failed to create orders directory: {}", e);
+            return split(get_fallback_orders());
+        }
+    }
 
-    if !found_file {
+    let files = read_orders_dir(orders_dir);
+    for error in &files.errors {
+        print_flush(print_log(t!("failed_to_load_orders", error = error), LogType::WARN));
+    }
+    orders = files.orders;
+
+    if !files.found_file {
         print!("Orders file not found. Do you want to create a orders file for OSC Clock? (Y/n): ");
         let mut input = String::new();
         io::stdout().flush().ok();
@@ -80,12 +192,107 @@ pub fn load_orders() -> Orders {
     split(orders)
 }
 
+// orders/<kind>_<name>, if it exists
+pub fn orders_subdir(kind: &str, name: &str) -> Option<PathBuf> {
+    // The name becomes part of a path, so only the characters of VRChat IDs are allowed
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return None;
+    }
+    let dir = Path::new("orders").join(format!("{}_{}", kind, name));
+    dir.is_dir().then_some(dir)
+}
+
+// The orders of the profile chosen over OSC, then of the current avatar, or the default ones
+fn active_orders_dir() -> PathBuf {
+    control::profile()
+        .as_deref()
+        .and_then(control::profile_dir)
+        .or_else(|| avatar::current().as_deref().and_then(avatar::orders_dir))
+        .unwrap_or_else(|| PathBuf::from("orders"))
+}
+
+// Reads the orders again without asking anything. Fails when any file is invalid
+pub fn reload_orders() -> Result<Orders, String> {
+    let files = read_orders_dir(&active_orders_dir());
+    if let Some(error) = files.errors.first() {
+        return Err(error.clone());
+    }
+    if !files.found_file {
+        return Err(t!("orders_file_not_found").to_string());
+    }
+    Ok(split(files.orders))
+}
+
+// Warns about destination names the config does not have
+pub fn check_destinations(order: &Order, destinations: &[Destination]) {
+    for name in order.destinations.iter().flatten() {
+        if !destinations.iter().any(|destination| &destination.name == name) {
+            print_flush(print_log(
+                t!("unknown_destination", destination = name, address = &order.address).to_string(),
+                LogType::WARN,
+            ));
+        }
+    }
+}
+
 fn split(orders: Vec<Order>) -> Orders {
     let mut sender = Vec::new();
     let mut handler = Vec::new();
+    let destinations = CONFIG.lock().unwrap().destinations();
 
-    for order in orders {
+    for mut order in orders {
+        check_destinations(&order, &destinations);
+        if let Some(timezone) = &order.timezone {
+            if Zone::parse(timezone).is_none() {
+                print_flush(print_log(
+                    t!("unknown_timezone", timezone = timezone, address = &order.address).to_string(),
+                    LogType::WARN,
+                ));
+            }
+        }
+        if order.transforms.iter().flatten().any(|transform| !transform.is_valid()) {
+            print_flush(print_log(
+                t!("ignored_order_parameter", parameter = "transforms", address = &order.address).to_string(),
+                LogType::WARN,
+            ));
+        }
+        if order.params.period_ms == Some(0) {
+            print_flush(print_log(
+                t!("ignored_order_parameter", parameter = "period_ms", address = &order.address).to_string(),
+                LogType::WARN,
+            ));
+        }
+        order.parse_times();
+        if order.r#type.needs_target() && order.target().is_none() {
+            print_flush(print_log(
+                t!("invalid_order_parameter", parameter = "target", address = &order.address).to_string(),
+                LogType::WARN,
+            ));
+        }
+        if order.r#type.needs_start() && order.start().is_none() {
+            print_flush(print_log(
+                t!("invalid_order_parameter", parameter = "start", address = &order.address).to_string(),
+                LogType::WARN,
+            ));
+        }
+        if order.r#type.needs_digit() {
+            for (parameter, missing) in [("field", order.params.field.is_none()), ("place", order.params.place.is_none())] {
+                if missing {
+                    print_flush(print_log(
+                        t!("invalid_order_parameter", parameter = parameter, address = &order.address).to_string(),
+                        LogType::WARN,
+                    ));
+                }
+            }
+        }
         if order.r#type == UnitType::UpdateHandler {
+            match AddressPattern::compile(&order.address) {
+                Ok(pattern) => order.pattern = Some(pattern),
+                Err(error) => print_flush(print_log(
+                    t!("invalid_address_pattern", error = error).to_string(),
+                    LogType::WARN,
+                )),
+            }
             handler.push(order);
         } else {
             sender.push(order);
@@ -100,77 +307,23 @@ fn split(orders: Vec<Order>) -> Orders {
 
 pub fn get_fallback_orders() -> Vec<Order> {
     vec![
-        Order {
-            r#type: UnitType::SecondFloat,
-            address: "/avatar/parameters/osc_clock@second_f".to_string(),
-        },
-        Order {
-            r#type: UnitType::SecondInt,
-            address: "/avatar/parameters/osc_clock@second_i".to_string(),
-        },
-        Order {
-            r#type: UnitType::MinuteFloatMixed,
-            address: "/avatar/parameters/osc_clock@minute_f".to_string(),
-        },
-        Order {
-            r#type: UnitType::MinuteInt,
-            address: "/avatar/parameters/osc_clock@minute_i".to_string(),
-        },
-        Order {
-            r#type: UnitType::Hour24FloatMixed,
-            address: "/avatar/parameters/osc_clock@hour24_f".to_string(),
-        },
-        Order {
-            r#type: UnitType::Hour24Int,
-            address: "/avatar/parameters/osc_clock@hour24_i".to_string(),
-        },
-        Order {
-            r#type: UnitType::Hour12FloatMixed,
-            address: "/avatar/parameters/osc_clock@hour12_f".to_string(),
-        },
-        Order {
-            r#type: UnitType::Hour12Int,
-            address: "/avatar/parameters/osc_clock@hour12_i".to_string(),
-        },
-        Order {
-            r#type: UnitType::IsPm,
-            address: "/avatar/parameters/osc_clock@hour_isPM".to_string(),
-        },
-        Order {
-            r#type: UnitType::DayInt,
-            address: "/avatar/parameters/osc_clock@day".to_string(),
-        },
-        Order {
-            r#type: UnitType::DayOfWeekInt,
-            address: "/avatar/parameters/osc_clock@dofw".to_string(),
-        },
-        Order {
-            r#type: UnitType::MonthInt,
-            address: "/avatar/parameters/osc_clock@month".to_string(),
-        },
-        Order {
-            r#type: UnitType::Year,
-            address: "/avatar/parameters/osc_clock@year".to_string(),
-        },
-        Order {
-            r#type: UnitType::Year0,
-            address: "/avatar/parameters/osc_clock@year_0".to_string(),
-        },
-        Order {
-            r#type: UnitType::Year1,
-            address: "/avatar/parameters/osc_clock@year_1".to_string(),
-        },
-        Order {
-            r#type: UnitType::Year2,
-            address: "/avatar/parameters/osc_clock@year_2".to_string(),
-        },
-        Order {
-            r#type: UnitType::Year3,
-            address: "/avatar/parameters/osc_clock@year_3".to_string(),
-        },
-        Order {
-            r#type: UnitType::UpdateHandler,
-            address: "/avatar/parameters/osc_clock@ForceSync".to_string(),
-        },
+        Order::new(UnitType::SecondFloat, "/avatar/parameters/osc_clock@second_f"),
+        Order::new(UnitType::SecondInt, "/avatar/parameters/osc_clock@second_i"),
+        Order::new(UnitType::MinuteFloatMixed, "/avatar/parameters/osc_clock@minute_f"),
+        Order::new(UnitType::MinuteInt, "/avatar/parameters/osc_clock@minute_i"),
+        Order::new(UnitType::Hour24FloatMixed, "/avatar/parameters/osc_clock@hour24_f"),
+        Order::new(UnitType::Hour24Int, "/avatar/parameters/osc_clock@hour24_i"),
+        Order::new(UnitType::Hour12FloatMixed, "/avatar/parameters/osc_clock@hour12_f"),
+        Order::new(UnitType::Hour12Int, "/avatar/parameters/osc_clock@hour12_i"),
+        Order::new(UnitType::IsPm, "/avatar/parameters/osc_clock@hour_isPM"),
+        Order::new(UnitType::DayInt, "/avatar/parameters/osc_clock@day"),
+        Order::new(UnitType::DayOfWeekInt, "/avatar/parameters/osc_clock@dofw"),
+        Order::new(UnitType::MonthInt, "/avatar/parameters/osc_clock@month"),
+        Order::new(UnitType::Year, "/avatar/parameters/osc_clock@year"),
+        Order::new(UnitType::Year0, "/avatar/parameters/osc_clock@year_0"),
+        Order::new(UnitType::Year1, "/avatar/parameters/osc_clock@year_1"),
+        Order::new(UnitType::Year2, "/avatar/parameters/osc_clock@year_2"),
+        Order::new(UnitType::Year3, "/avatar/parameters/osc_clock@year_3"),
+        Order::new(UnitType::UpdateHandler, "/avatar/parameters/osc_clock@ForceSync"),
     ]
 }
@@ -1,21 +1,46 @@
 use chrono::{ Local };
-use vrchat_osc::{ models::OscRootNode, Error, ServiceType, VRChatOSC };
-use vrchat_osc::rosc::{ OscMessage, OscPacket };
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{ Mutex, Notify };
+use vrchat_osc::{ Error, ServiceType, VRChatOSC };
+use vrchat_osc::models::{ AccessMode, OscNode, OscRootNode, OscType, OscTypeTag, OscValue, RangeInfo };
+use vrchat_osc::rosc::OscPacket;
 
+use crate::clock::OffsetClock;
 use crate::log::{ print_log, print_flush, LogType };
-use crate::config::{ CONFIG };
-use crate::message::SyncFlag;
-use crate::order::ORDERS;
-use crate::receiver::check;
-use crate::sender::{sender, send};
-use crate::message::{ build, BuilderParams};
-
-pub async fn start() -> Result<(), Error> {
+use crate::config::{ Destination, CONFIG, DEFAULT_DESTINATION };
+use crate::order::{ Orders, ORDERS };
+use crate::pattern::AddressPattern;
+use crate::receiver::handle_packet;
+use crate::sender::{ sender, send };
+use crate::services::{ self, ServiceKind };
+use crate::shutdown::Tasks;
+use crate::transform;
+use crate::unit::ValueKind;
+
+// Other instances answer the mDNS query sent on start within this time, so their names are known.
+// One that answers later, or starts at the same time, may still end up with the same name
+const NAME_PROBE: Duration = Duration::from_secs(1);
+
+// The registered service and the tree it advertises, so the tree can follow the orders
+struct Registration {
+    vrchat_osc: Arc<VRChatOSC>,
+    clock: Arc<OffsetClock>,
+    name: String,
+    tree: serde_json::Value,
+}
+
+static REGISTRATION: Lazy<Mutex<Option<Registration>>> = Lazy::new(|| Mutex::new(None));
+
+// Starts the OSCQuery service. `found` is notified whenever the target service is found on the network
+pub async fn connect(found: Arc<Notify>, clock: Arc<OffsetClock>) -> Result<Arc<VRChatOSC>, Error> {
     let vrchat_osc = VRChatOSC::new(None).await?;
 
     vrchat_osc.on_connect(move |res| {
         match res {
             ServiceType::Osc(name, addr) => {
+                services::discovered(ServiceKind::Osc, &name, addr);
                 print_flush(
                     print_log(
                         t!("on_connect_to_osc_server", name = name, address = addr),
@@ -24,46 +49,103 @@ pub async fn start() -> Result<(), Error> {
                 );
             }
             ServiceType::OscQuery(name, addr) => {
+                let instance = services::discovered(ServiceKind::OscQuery, &name, addr);
                 print_flush(
                     print_log(
                         t!("on_connect_to_osc_query_server", name = name, address = addr),
                         LogType::INFO
                     )
                 );
+                if services::is_target(&CONFIG.lock().unwrap().osc_query_target, &instance) {
+                    found.notify_one();
+                }
             }
         }
     }).await;
 
-    let service_name = "osc-clock";
-    let root_node = OscRootNode::new().with_avatar();
-    vrchat_osc.register(&service_name, root_node, |packet| {
-        if let OscPacket::Message(msg) = packet {
-            let config = CONFIG.lock().unwrap().clone();
-            if check(msg.clone(), ORDERS.clone().handler) {
-                let flag = SyncFlag::MINUTE | SyncFlag::HOUR | SyncFlag::DAY;
-                let messages = build(BuilderParams {
-                    orders: ORDERS.clone().sender,
-                    sync_flag: flag,
-                });
-                for message in messages {
-                    send(message, &config.sender_ip, config.sender_port);
-                }
-            }
-        }
+    tokio::time::sleep(NAME_PROBE).await;
+    let service_name = services::unique_name(&CONFIG.lock().unwrap().osc_query_service_name);
+    let root_node = root_node(&ORDERS.lock().unwrap());
+    *REGISTRATION.lock().await = Some(register(vrchat_osc.clone(), clock, service_name, root_node).await?);
+
+    Ok(vrchat_osc)
+}
+
+async fn register(
+    vrchat_osc: Arc<VRChatOSC>,
+    clock: Arc<OffsetClock>,
+    name: String,
+    root_node: OscRootNode
+) -> Result<Registration, Error> {
+    let tree = tree(&root_node);
+    let handler_clock = clock.clone();
+    vrchat_osc.register(&name, root_node, move |packet| {
+        handle_packet(packet, &handler_clock);
     }).await?;
 
-    print_flush(print_log(t!("osc_query_service_registered", name = &service_name), LogType::INFO));
+    print_flush(print_log(t!("osc_query_service_registered", name = &name), LogType::INFO));
+
+    Ok(Registration { vrchat_osc, clock, name, tree })
+}
 
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+fn tree(root_node: &OscRootNode) -> serde_json::Value {
+    serde_json::to_value(root_node.get_node("/")).unwrap_or_default()
+}
 
-    let cloned_vrchat_osc = vrchat_osc.clone();
-    let sender_task = tokio::spawn(async move {
-        let send = |m: OscMessage| {
-            let cloned_vrchat_osc = cloned_vrchat_osc.clone();
+// Registers the service again when the loaded orders change what it advertises
+pub fn refresh() {
+    tokio::spawn(async {
+        let mut registration = REGISTRATION.lock().await;
+        let Some(current) = registration.as_ref() else {
+            return;
+        };
+        let root_node = root_node(&ORDERS.lock().unwrap());
+        if tree(&root_node) == current.tree {
+            return;
+        }
+        // The tree cannot be changed in place, so the service is registered again under the same name
+        let (vrchat_osc, clock, name) = (current.vrchat_osc.clone(), current.clock.clone(), current.name.clone());
+        let result = async {
+            vrchat_osc.unregister(&name).await?;
+            register(vrchat_osc.clone(), clock, name, root_node).await
+        }.await;
+        match result {
+            Ok(new) => *registration = Some(new),
+            Err(error) => {
+                *registration = None;
+                print_flush(
+                    print_log(t!("osc_query_failed", error = format!("{:?}", error)), LogType::WARN)
+                );
+            }
+        }
+    });
+}
+
+// Sends to VRChat found with OSCQuery, until the task is stopped
+pub fn spawn_sender(vrchat_osc: Arc<VRChatOSC>, clock: Arc<OffsetClock>) -> Tasks {
+    let mut tasks = Tasks::default();
+    tasks.spawn(|mut stopping| async move {
+        tokio::select! {
+            _ = tokio::time::sleep(tokio::time::Duration::from_secs(2)) => {}
+            _ = stopping.wait_for(|stopping| *stopping) => return,
+        }
+
+        let send = |packet: OscPacket, destination: Destination| {
+            let cloned_vrchat_osc = vrchat_osc.clone();
             async move {
-                cloned_vrchat_osc
-                    .send(OscPacket::Message(m.clone()), "VRChat-Client-*").await
-                    .unwrap();
+                if destination.name != DEFAULT_DESTINATION {
+                    return send(packet, &destination).await.is_ok();
+                }
+                let target = CONFIG.lock().unwrap().osc_query_target.clone();
+                if let Err(error) = cloned_vrchat_osc.send(packet, &target).await {
+                    print_flush(
+                        print_log(
+                            t!("send_failed_osc_query", error = format!("{:?}", error)),
+                            LogType::WARN
+                        )
+                    );
+                    return false;
+                }
                 if CONFIG.lock().unwrap().show_debug_log {
                     let str = t!(
                         "debug_on_send_message_osc_query",
@@ -71,14 +153,103 @@ pub async fn start() -> Result<(), Error> {
                     );
                     print_flush(print_log(str, LogType::SEND));
                 }
+                true
             }
         };
-        sender(send).await;
+        sender(send, stopping, clock).await;
     });
+    tasks
+}
+
+// Describes the addresses sent by the orders (readable) and received by the handlers (writable)
+fn root_node(orders: &Orders) -> OscRootNode {
+    let mut root_node = OscRootNode::new().with_avatar();
+    for order in &orders.sender {
+        let kind = transform::value_kind(order.r#type.value_kind(), order.transforms.as_deref().unwrap_or_default());
+        let (r#type, range) = match kind {
+            ValueKind::Int { min, max } => {
+                let range = (min.is_some() || max.is_some()).then(|| RangeInfo {
+                    min: min.map(OscValue::Int),
+                    max: max.map(OscValue::Int),
+                    vals: None,
+                });
+                (OscType::Int32, range)
+            }
+            ValueKind::Float { min, max } => {
+                let range = (min.is_some() || max.is_some()).then(|| RangeInfo {
+                    min: min.map(|min| OscValue::Float(min as f64)),
+                    max: max.map(|max| OscValue::Float(max as f64)),
+                    vals: None,
+                });
+                (OscType::Float32, range)
+            }
+            ValueKind::Bool => (OscType::True, None),
+            ValueKind::None => continue,
+        };
+        root_node = root_node.add_node(OscNode {
+            full_path: order.address.clone(),
+            r#type: Some(OscTypeTag::new(vec![r#type])),
+            access: AccessMode::ReadOnly,
+            range: range.map(|range| vec![range]),
+            ..Default::default()
+        });
+    }
+    for order in &orders.handler {
+        // {foo,bar} lists its addresses, but the other patterns match too many to list.
+        // An address that is not a valid pattern is matched exactly
+        let addresses = match AddressPattern::compile(&order.address) {
+            Ok(pattern) => pattern.addresses().unwrap_or_default(),
+            Err(_) => vec![order.address.clone()],
+        };
+        for address in addresses {
+            let access = match root_node.get_node(&address).map(|node| node.access) {
+                Some(AccessMode::ReadOnly | AccessMode::ReadWrite) => AccessMode::ReadWrite,
+                _ => AccessMode::WriteOnly,
+            };
+            root_node = root_node.add_node(OscNode {
+                full_path: address,
+                access,
+                ..Default::default()
+            });
+        }
+    }
+    root_node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::Order;
+    use crate::unit::UnitType;
+
+    #[test]
+    fn describes_orders() {
+        let orders = Orders {
+            sender: vec![
+                Order::new(UnitType::Hour24Int, "/avatar/parameters/hour"),
+                Order::new(UnitType::IsPm, "/avatar/parameters/pm"),
+            ],
+            handler: vec![
+                Order::new(UnitType::UpdateHandler, "/avatar/parameters/AFK"),
+                Order::new(UnitType::UpdateHandler, "/avatar/parameters/pm"),
+                Order::new(UnitType::UpdateHandler, "/avatar/parameters/{a,b}"),
+                Order::new(UnitType::UpdateHandler, "/avatar/parameters/Mute*"),
+            ],
+        };
+        let root_node = root_node(&orders);
 
-    tokio::signal::ctrl_c().await?;
-    sender_task.abort();
-    vrchat_osc.shutdown().await?;
+        let hour = root_node.get_node("/avatar/parameters/hour").unwrap();
+        assert!(hour.is_type(&OscType::Int32));
+        assert_eq!(hour.access, AccessMode::ReadOnly);
+        let range = &hour.range.as_ref().unwrap()[0];
+        assert_eq!((range.min.clone(), range.max.clone()), (Some(OscValue::Int(0)), Some(OscValue::Int(23))));
 
-    Ok(())
+        assert!(root_node.get_node("/avatar/parameters/pm").unwrap().is_boolean_type());
+        assert_eq!(root_node.get_node("/avatar/parameters/pm").unwrap().access, AccessMode::ReadWrite);
+        assert_eq!(root_node.get_node("/avatar/parameters/AFK").unwrap().access, AccessMode::WriteOnly);
+        assert!(root_node.get_node("/avatar/parameters/{a,b}").is_none());
+        assert_eq!(root_node.get_node("/avatar/parameters/a").unwrap().access, AccessMode::WriteOnly);
+        assert_eq!(root_node.get_node("/avatar/parameters/b").unwrap().access, AccessMode::WriteOnly);
+        assert!(root_node.get_node("/avatar/parameters/Mute*").is_none());
+    }
 }
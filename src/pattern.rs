@@ -0,0 +1,222 @@
+// OSC 1.0 address patterns: `*`, `?`, `[a-z]`, `[!a-z]` and `{foo,bar}` within each part of the address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressPattern {
+    parts: Vec<Vec<Token>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    // ?
+    AnyChar,
+    // *
+    AnySequence,
+    // [...] or [!...]
+    Class { negated: bool, ranges: Vec<(char, char)> },
+    // {...,...}
+    Choice(Vec<Vec<char>>),
+}
+
+impl AddressPattern {
+    pub fn compile(pattern: &str) -> Result<Self, String> {
+        let Some(path) = pattern.strip_prefix('/') else {
+            return Err(format!("{} does not start with /", pattern));
+        };
+        let parts = path
+            .split('/')
+            .map(|part| compile_part(part).map_err(|error| format!("{} ({})", error, pattern)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { parts })
+    }
+
+    pub fn matches(&self, address: &str) -> bool {
+        let Some(path) = address.strip_prefix('/') else {
+            return false;
+        };
+        let parts: Vec<&str> = path.split('/').collect();
+        parts.len() == self.parts.len() &&
+            self.parts
+                .iter()
+                .zip(parts)
+                .all(|(tokens, part)| match_part(tokens, &part.chars().collect::<Vec<_>>()))
+    }
+}
+
+impl AddressPattern {
+    // Every address the pattern matches, when it is made of literals and {foo,bar} choices only
+    pub fn addresses(&self) -> Option<Vec<String>> {
+        let mut addresses = vec![String::new()];
+        for tokens in &self.parts {
+            for address in addresses.iter_mut() {
+                address.push('/');
+            }
+            for token in tokens {
+                let choices: Vec<String> = match token {
+                    Token::Literal(c) => vec![c.to_string()],
+                    Token::Choice(choices) => choices.iter().map(|choice| choice.iter().collect()).collect(),
+                    _ => return None,
+                };
+                addresses = addresses
+                    .iter()
+                    .flat_map(|address| choices.iter().map(move |choice| format!("{}{}", address, choice)))
+                    .collect();
+            }
+        }
+        Some(addresses)
+    }
+}
+
+fn compile_part(part: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = part.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                // Consecutive stars match the same as one
+                if tokens.last() != Some(&Token::AnySequence) {
+                    tokens.push(Token::AnySequence);
+                }
+            }
+            '?' => tokens.push(Token::AnyChar),
+            '[' => {
+                let mut class: Vec<char> = Vec::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) => class.push(c),
+                        None => return Err("unclosed [".to_string()),
+                    }
+                }
+                let negated = class.first() == Some(&'!');
+                if negated {
+                    class.remove(0);
+                }
+                if class.is_empty() {
+                    return Err("empty []".to_string());
+                }
+                let mut ranges = Vec::new();
+                let mut i = 0;
+                while i < class.len() {
+                    // A - at either end is a literal -
+                    if i + 2 < class.len() && class[i + 1] == '-' {
+                        ranges.push((class[i].min(class[i + 2]), class[i].max(class[i + 2])));
+                        i += 3;
+                    } else {
+                        ranges.push((class[i], class[i]));
+                        i += 1;
+                    }
+                }
+                tokens.push(Token::Class { negated, ranges });
+            }
+            '{' => {
+                let mut choice = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => choice.push(c),
+                        None => return Err("unclosed {".to_string()),
+                    }
+                }
+                tokens.push(Token::Choice(choice.split(',').map(|s| s.chars().collect()).collect()));
+            }
+            c => tokens.push(Token::Literal(c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn match_part(tokens: &[Token], chars: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return chars.is_empty();
+    };
+    match token {
+        Token::Literal(c) => chars.first() == Some(c) && match_part(rest, &chars[1..]),
+        Token::AnyChar => !chars.is_empty() && match_part(rest, &chars[1..]),
+        Token::AnySequence => (0..=chars.len()).any(|n| match_part(rest, &chars[n..])),
+        Token::Class { negated, ranges } =>
+            match chars.first() {
+                Some(c) => {
+                    let inside = ranges.iter().any(|(low, high)| low <= c && c <= high);
+                    inside != *negated && match_part(rest, &chars[1..])
+                }
+                None => false,
+            }
+        Token::Choice(choices) =>
+            choices
+                .iter()
+                .any(|choice| chars.starts_with(choice) && match_part(rest, &chars[choice.len()..])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, address: &str) -> bool {
+        AddressPattern::compile(pattern).unwrap().matches(address)
+    }
+
+    #[test]
+    fn literal() {
+        assert!(matches("/avatar/parameters/AFK", "/avatar/parameters/AFK"));
+        assert!(!matches("/avatar/parameters/AFK", "/avatar/parameters/AFK2"));
+        assert!(!matches("/avatar/parameters/AFK", "/avatar/parameters"));
+        assert!(!matches("/avatar/parameters/AFK", "/avatar/parameters/AFK/x"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("/avatar/parameters/*", "/avatar/parameters/AFK"));
+        assert!(matches("/avatar/parameters/*", "/avatar/parameters/"));
+        assert!(matches("/avatar/*/AFK", "/avatar/parameters/AFK"));
+        // * does not cross a /
+        assert!(!matches("/avatar/*", "/avatar/parameters/AFK"));
+        assert!(matches("/avatar/parameters/Mute*", "/avatar/parameters/MuteSelf"));
+        assert!(matches("/avatar/parameters/*Self", "/avatar/parameters/MuteSelf"));
+        assert!(matches("/avatar/parameters/A?K", "/avatar/parameters/AFK"));
+        assert!(!matches("/avatar/parameters/A?K", "/avatar/parameters/AK"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("/input/[a-c]", "/input/b"));
+        assert!(!matches("/input/[a-c]", "/input/d"));
+        assert!(matches("/input/[!a-c]", "/input/d"));
+        assert!(!matches("/input/[!a-c]", "/input/a"));
+        assert!(matches("/input/[xyz]", "/input/y"));
+        assert!(matches("/input/[a-]", "/input/-"));
+        assert!(matches("/input/[0-9][0-9]", "/input/42"));
+    }
+
+    #[test]
+    fn choices() {
+        let pattern = "/avatar/parameters/{AFK,MuteSelf,Earmuffs}";
+        assert!(matches(pattern, "/avatar/parameters/AFK"));
+        assert!(matches(pattern, "/avatar/parameters/MuteSelf"));
+        assert!(matches(pattern, "/avatar/parameters/Earmuffs"));
+        assert!(!matches(pattern, "/avatar/parameters/Grounded"));
+        assert!(!matches(pattern, "/avatar/parameters/AFKX"));
+        assert!(matches("/a/{b,bc}d", "/a/bcd"));
+    }
+
+    #[test]
+    fn lists_choices() {
+        let addresses = |pattern: &str| AddressPattern::compile(pattern).unwrap().addresses();
+        assert_eq!(addresses("/avatar/parameters/AFK"), Some(vec!["/avatar/parameters/AFK".to_string()]));
+        assert_eq!(
+            addresses("/{a,b}/x{1,2}"),
+            Some(vec!["/a/x1".to_string(), "/a/x2".to_string(), "/b/x1".to_string(), "/b/x2".to_string()])
+        );
+        assert_eq!(addresses("/avatar/parameters/*"), None);
+        assert_eq!(addresses("/input/[a-c]"), None);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(AddressPattern::compile("avatar/parameters").is_err());
+        assert!(AddressPattern::compile("/avatar/[a-z").is_err());
+        assert!(AddressPattern::compile("/avatar/{a,b").is_err());
+        assert!(AddressPattern::compile("/avatar/[]").is_err());
+    }
+}
@@ -1,15 +1,27 @@
 use chrono::Local;
-use vrchat_osc::rosc::{ OscMessage, OscPacket, OscType };
-use std::net::{ UdpSocket, SocketAddr };
+use vrchat_osc::rosc::{ OscMessage, OscPacket, OscTime, OscType };
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{ Duration, Instant, SystemTime };
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
 
+use crate::avatar::{ self, AVATAR_CHANGE_ADDRESS };
+use crate::clock::{ Clock, OffsetClock };
 use crate::config::{ CONFIG };
+use crate::control::{ self, CONTROL_PREFIX };
 use crate::log::{ print_log, print_flush, LogType };
-use crate::message::{ build, BuilderParams, SyncFlag };
+use crate::message::{ build, BuilderParams };
 use crate::order::{Order, ORDERS};
-use crate::sender::send;
+use crate::sender::send_all;
+use crate::trigger::HANDLER_STATES;
 
-pub async fn receiver() {
-    let mut config = CONFIG.lock().unwrap().clone();
+// Longest a bundle is held back for its timetag
+const MAX_BUNDLE_DELAY: Duration = Duration::from_secs(10);
+
+// Runs until `stopping` changes to true, so the port is free for the next receiver
+pub async fn receiver(mut stopping: watch::Receiver<bool>, clock: Arc<OffsetClock>) {
+    let config = CONFIG.lock().unwrap().clone();
 
     let receiver_address: SocketAddr = (
         config.receiver_ip.to_string() +
@@ -17,43 +29,25 @@ pub async fn receiver() {
         &config.receiver_port.to_string()
     )
         .parse()
-        .expect(&print_log("Failed to parse address".to_string(), LogType::ERROR));
-    let socket = UdpSocket::bind(receiver_address).expect(
-        &print_log("Failed to bind socket".to_string(), LogType::ERROR)
+        .unwrap_or_else(|error| panic!("{}: {:?}", print_log("Failed to parse address".to_string(), LogType::ERROR), error));
+    let socket = UdpSocket::bind(receiver_address).await.unwrap_or_else(|error|
+        panic!("{}: {:?}", print_log("Failed to bind socket".to_string(), LogType::ERROR), error)
     );
 
     print_flush(print_log(t!("listening_to_N", address = receiver_address), LogType::INFO));
 
     loop {
         let mut buf = [0; 2048];
-        let (size, _) = socket
-            .recv_from(&mut buf)
-            .expect(&print_log(t!("failed_to_receive_data").to_string(), LogType::ERROR));
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buf) => received,
+            _ = stopping.wait_for(|stopping| *stopping) => break,
+        };
+        let (size, _) = received.unwrap_or_else(|error|
+            panic!("{}: {:?}", print_log(t!("failed_to_receive_data").to_string(), LogType::ERROR), error)
+        );
         match vrchat_osc::rosc::decoder::decode_udp(&buf[..size]) {
-            Ok(packet) => {
-                match packet {
-                    (_, OscPacket::Message(msg)) => {
-                        config = CONFIG.lock().unwrap().clone();
-                        if check(msg.clone(), ORDERS.clone().handler) {
-                            let flag = SyncFlag::MINUTE | SyncFlag::HOUR | SyncFlag::DAY;
-                            let messages = build(BuilderParams {
-                                orders: ORDERS.clone().sender,
-                                sync_flag: flag,
-                            });
-                            for message in messages {
-                                send(message, &config.sender_ip, config.sender_port);
-                            }
-                        }
-                    }
-                    (_, OscPacket::Bundle(bundle)) => {
-                        print_flush(
-                            print_log(
-                                t!("received_osc_bundle", bundle = format!("{:?}", bundle)),
-                                LogType::INFO
-                            )
-                        );
-                    }
-                }
+            Ok((_, packet)) => {
+                handle_packet(packet, &clock);
             }
             Err(err) => {
                 print_flush(
@@ -67,46 +61,199 @@ pub async fn receiver() {
     }
 }
 
-pub fn check(msg: OscMessage, order: Vec<Order>) -> bool {
-    let update: bool;
-
-    match msg.args[0] {
-        OscType::Bool(b) => {
-            if b {
-                update = true;
-            } else {
-                update = false;
+// Runs every message of a packet through the handlers, waiting for bundles that are timed in the future
+pub fn handle_packet(packet: OscPacket, clock: &Arc<OffsetClock>) {
+    if let OscPacket::Bundle(bundle) = &packet {
+        if CONFIG.lock().unwrap().show_debug_log {
+            print_flush(
+                print_log(t!("received_osc_bundle", bundle = format!("{:?}", bundle)), LogType::INFO)
+            );
+        }
+    }
+    for (delay, msg) in unpack(packet, SystemTime::now()) {
+        if delay.is_zero() {
+            handle_message(msg, clock);
+        } else {
+            let clock = clock.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                handle_message(msg, &clock);
+            });
+        }
+    }
+}
+
+// Messages of a packet and how long to wait before handling each of them
+fn unpack(packet: OscPacket, now: SystemTime) -> Vec<(Duration, OscMessage)> {
+    let mut messages = Vec::new();
+    unpack_into(packet, now, Duration::ZERO, &mut messages);
+    messages
+}
+
+fn unpack_into(packet: OscPacket, now: SystemTime, delay: Duration, messages: &mut Vec<(Duration, OscMessage)>) {
+    match packet {
+        OscPacket::Message(msg) => messages.push((delay, msg)),
+        OscPacket::Bundle(bundle) => {
+            // Past and "immediately" timetags give zero. A nested bundle cannot run before its parent,
+            // and a timetag far ahead is most likely a clock that is off, so the wait is capped
+            let delay = delay.max(until(bundle.timetag, now)).min(MAX_BUNDLE_DELAY);
+            for packet in bundle.content {
+                unpack_into(packet, now, delay, messages);
             }
         }
-        _ => {
+    }
+}
+
+fn until(timetag: OscTime, now: SystemTime) -> Duration {
+    // Only timetags after now are converted, as rosc cannot convert times before 1970
+    match OscTime::try_from(now) {
+        Ok(now_tag) if timetag > now_tag => SystemTime::from(timetag).duration_since(now).unwrap_or_default(),
+        _ => Duration::ZERO,
+    }
+}
+
+fn handle_message(msg: OscMessage, clock: &OffsetClock) {
+    if let Some(command) = msg.addr.strip_prefix(CONTROL_PREFIX) {
+        control::handle(command, &msg.args, clock);
+        return;
+    }
+    // A new avatar starts from its default parameter values, so everything is sent again
+    if msg.addr == AVATAR_CHANGE_ADDRESS {
+        if let Some(OscType::String(avatar_id)) = msg.args.first() {
+            avatar::change(avatar_id);
+        }
+        full_sync(clock);
+        return;
+    }
+    if check(msg, ORDERS.lock().unwrap().clone().handler) {
+        full_sync(clock);
+    }
+}
+
+// Sends every value, whether it changed or not. Returns false and sends nothing while paused
+pub fn full_sync(clock: &dyn Clock) -> bool {
+    if control::is_paused() {
+        return false;
+    }
+    let config = CONFIG.lock().unwrap().clone();
+    let orders = ORDERS.lock().unwrap().clone().sender;
+    let messages = build(BuilderParams {
+        orders: &orders,
+        now: clock.now(),
+    })
+        .into_iter()
+        .map(|(_, message)| message)
+        .collect();
+    tokio::spawn(async move {
+        send_all(messages, &orders, &config).await;
+    });
+    true
+}
+
+pub fn check(msg: OscMessage, order: Vec<Order>) -> bool {
+    let mut states = HANDLER_STATES.lock().unwrap();
+    let now = Instant::now();
+    let mut update = false;
+
+    // Every matching handler sees the value, so each keeps track of its own edges
+    for handler in order.iter().filter(|handler| handler.matches(&msg.addr)) {
+        if states.fires(handler, &msg.addr, msg.args.first(), now) {
             update = true;
         }
     }
+
     if update {
-        for n in 0..order.len() {
-            if msg.addr.to_string() == order[n].address.to_string() {
-                print_flush(
-                    print_log(
-                        t!(
-                            "on_receive_packet_from_specific_address",
-                            address = msg.addr.to_string()
-                        ),
-                        LogType::EVENT
-                    )
-                );
-                print_flush(
-                    print_log(
-                        t!(
-                            "parameters_synced",
-                            timestamp = Local::now().format("%Y-%m-%d %H:%M:%S")
-                        ),
-                        LogType::SEND
-                    )
-                );
-                return true;
-            }
-        }
+        print_flush(
+            print_log(
+                t!(
+                    "on_receive_packet_from_specific_address",
+                    address = msg.addr.to_string()
+                ),
+                LogType::EVENT
+            )
+        );
+        print_flush(
+            print_log(
+                t!(
+                    "parameters_synced",
+                    timestamp = Local::now().format("%Y-%m-%d %H:%M:%S")
+                ),
+                LogType::SEND
+            )
+        );
     }
+    update
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vrchat_osc::rosc::OscBundle;
+
+    const IMMEDIATELY: OscTime = OscTime { seconds: 0, fractional: 1 };
 
-    return false;
+    fn message(address: &str) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: address.to_string(),
+            args: vec![OscType::Bool(true)],
+        })
+    }
+
+    fn bundle(timetag: OscTime, content: Vec<OscPacket>) -> OscPacket {
+        OscPacket::Bundle(OscBundle { timetag, content })
+    }
+
+    fn at(now: SystemTime, seconds: u64) -> OscTime {
+        OscTime::try_from(now + Duration::from_secs(seconds)).unwrap()
+    }
+
+    fn delays(packet: OscPacket, now: SystemTime) -> Vec<(u64, String)> {
+        unpack(packet, now)
+            .into_iter()
+            .map(|(delay, msg)| ((delay.as_secs_f64().round() as u64), msg.addr))
+            .collect()
+    }
+
+    #[test]
+    fn unpacks_nested_bundles_in_order() {
+        let now = SystemTime::now();
+        let packet = bundle(IMMEDIATELY, vec![
+            message("/a"),
+            bundle(IMMEDIATELY, vec![message("/b"), bundle(IMMEDIATELY, vec![message("/c")])]),
+            message("/d"),
+        ]);
+        assert_eq!(delays(packet, now), vec![
+            (0, "/a".to_string()),
+            (0, "/b".to_string()),
+            (0, "/c".to_string()),
+            (0, "/d".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn waits_for_future_timetags() {
+        let now = SystemTime::now();
+        let packet = bundle(at(now, 2), vec![
+            message("/a"),
+            // A nested bundle timed before its parent still waits for the parent
+            bundle(IMMEDIATELY, vec![message("/b")]),
+            bundle(at(now, 5), vec![message("/c")]),
+        ]);
+        assert_eq!(delays(packet, now), vec![
+            (2, "/a".to_string()),
+            (2, "/b".to_string()),
+            (5, "/c".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn past_and_far_timetags() {
+        let now = SystemTime::now();
+        let past = OscTime::try_from(now - Duration::from_secs(60)).unwrap();
+        assert_eq!(delays(bundle(past, vec![message("/a")]), now), vec![(0, "/a".to_string())]);
+        assert_eq!(
+            delays(bundle(at(now, 3600), vec![message("/a")]), now),
+            vec![(MAX_BUNDLE_DELAY.as_secs(), "/a".to_string())]
+        );
+    }
 }
@@ -0,0 +1,178 @@
+use serde_json::Value;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, SystemTime };
+
+use crate::config::{ check_config, read_config_json, CONFIG };
+use crate::log::{ print_flush, print_log, LogType };
+use crate::order::{ self, Order, ORDERS };
+use crate::osc_query;
+use crate::schedule::{ self, SCHEDULES };
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Settings that are only read when osc_clock starts
+const RESTART_REQUIRED: [&str; 4] = ["use_osc_query", "osc_query_service_name", "receiver_ip", "receiver_port"];
+
+type Fingerprint = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+fn fingerprint(path: &Path) -> Fingerprint {
+    let mut files = Vec::new();
+    collect(path, &mut files);
+    files.sort();
+    files
+}
+
+fn collect(path: &Path, files: &mut Fingerprint) {
+    if path.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                collect(&entry.path(), files);
+            }
+        }
+    } else if let Ok(metadata) = fs::metadata(path) {
+        files.push((path.to_path_buf(), metadata.modified().ok(), metadata.len()));
+    }
+}
+
+// Polls config.json and the orders directory and reloads whatever changed
+pub async fn watch() {
+    let config_path = Path::new("./config.json");
+    let orders_path = Path::new("orders");
+    let mut config_fingerprint = fingerprint(config_path);
+    let mut orders_fingerprint = fingerprint(orders_path);
+
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let current = fingerprint(config_path);
+        if current != config_fingerprint {
+            config_fingerprint = current;
+            reload_config();
+        }
+
+        let current = fingerprint(orders_path);
+        if current != orders_fingerprint {
+            orders_fingerprint = current;
+            reload_orders();
+        }
+    }
+}
+
+pub fn reload_all() -> bool {
+    let config = reload_config();
+    let orders = reload_orders();
+    config && orders
+}
+
+pub fn reload_config() -> bool {
+    reload_config_from(Path::new("./config.json"))
+}
+
+// Swaps in the config file if it is valid, otherwise keeps the running config
+fn reload_config_from(path: &Path) -> bool {
+    let new_config = match read_config_json(&path.to_string_lossy(), true) {
+        Ok(config) => config,
+        Err(error) => {
+            print_flush(print_log(t!("reload_failed", file = "config.json", error = error), LogType::WARN));
+            return false;
+        }
+    };
+
+    let old_config = CONFIG.lock().unwrap().clone();
+    let old_value = serde_json::to_value(&old_config).unwrap_or(Value::Null);
+    let new_value = serde_json::to_value(&new_config).unwrap_or(Value::Null);
+
+    if let (Value::Object(old_map), Value::Object(new_map)) = (&old_value, &new_value) {
+        for (key, new) in new_map {
+            if key == "config_status" || old_map.get(key) == Some(new) {
+                continue;
+            }
+            let old = old_map.get(key).unwrap_or(&Value::Null);
+            print_flush(
+                print_log(
+                    t!("config_value_changed", key = key, old = old, new = new),
+                    LogType::INFO
+                )
+            );
+            if RESTART_REQUIRED.contains(&key.as_str()) {
+                print_flush(print_log(t!("config_change_needs_restart", key = key), LogType::WARN));
+            }
+        }
+    }
+
+    if new_config.language != old_config.language {
+        rust_i18n::set_locale(&new_config.language);
+    }
+    check_config(&new_config);
+    let destinations = new_config.destinations();
+    *CONFIG.lock().unwrap() = new_config;
+
+    // Orders may name a destination that was just removed
+    let orders = ORDERS.lock().unwrap().clone();
+    for order in orders.sender.iter().chain(orders.handler.iter()) {
+        order::check_destinations(order, &destinations);
+    }
+    true
+}
+
+// Swaps in the orders and schedules if every file is valid, otherwise keeps the running ones
+pub fn reload_orders() -> bool {
+    let new_orders = match order::reload_orders() {
+        Ok(orders) => orders,
+        Err(error) => {
+            print_flush(print_log(t!("reload_failed", file = "orders", error = error), LogType::WARN));
+            return false;
+        }
+    };
+    let new_schedules = match schedule::reload_schedules() {
+        Ok(schedules) => schedules,
+        Err(error) => {
+            print_flush(print_log(t!("reload_failed", file = "orders", error = error), LogType::WARN));
+            return false;
+        }
+    };
+
+    let old_orders = ORDERS.lock().unwrap().clone();
+    let old: Vec<&Order> = old_orders.sender.iter().chain(old_orders.handler.iter()).collect();
+    let new: Vec<&Order> = new_orders.sender.iter().chain(new_orders.handler.iter()).collect();
+    for order in old.iter().filter(|order| !new.contains(order)) {
+        print_flush(print_log(t!("order_removed", address = &order.address), LogType::INFO));
+    }
+    for order in new.iter().filter(|order| !old.contains(order)) {
+        print_flush(print_log(t!("order_added", address = &order.address), LogType::INFO));
+    }
+
+    print_flush(
+        print_log(
+            t!(
+                "orders_reloaded",
+                sender = new_orders.sender.len(),
+                handler = new_orders.handler.len(),
+                schedule = new_schedules.len()
+            ),
+            LogType::INFO
+        )
+    );
+    *ORDERS.lock().unwrap() = new_orders;
+    *SCHEDULES.lock().unwrap() = new_schedules;
+    // Avatar and profile changes come here too, so the advertised tree always matches the orders
+    osc_query::refresh();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_config_keeps_the_running_one() {
+        let path = std::env::temp_dir().join(format!("osc_clock_invalid_config_{}.json", std::process::id()));
+        fs::write(&path, "{ \"tick_interval_ms\": ").unwrap();
+        let before = serde_json::to_value(&*CONFIG.lock().unwrap()).unwrap();
+
+        assert!(!reload_config_from(&path));
+        assert_eq!(serde_json::to_value(&*CONFIG.lock().unwrap()).unwrap(), before);
+        let _ = fs::remove_file(&path);
+    }
+}
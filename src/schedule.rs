@@ -0,0 +1,449 @@
+use chrono::{ DateTime, Datelike, Duration, FixedOffset, Timelike, Utc };
+use once_cell::sync::Lazy;
+use serde::{ Deserialize, Serialize };
+use std::fs;
+use std::path::Path;
+use std::sync::{ Arc, Mutex };
+use vrchat_osc::rosc::{ OscMessage, OscType };
+
+use crate::clock::Clock;
+use crate::log::{ print_flush, print_log, LogType };
+use crate::message::make_message;
+use crate::timezone::Zone;
+
+// Minute boundaries checked at most after the clock jumped forward
+const MAX_CATCH_UP_MINUTES: i32 = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScheduleValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+impl ScheduleValue {
+    pub fn to_osc(&self) -> OscType {
+        match self {
+            ScheduleValue::Bool(b) => OscType::Bool(*b),
+            ScheduleValue::Int(i) => OscType::Int(*i),
+            ScheduleValue::Float(f) => OscType::Float(*f),
+            ScheduleValue::String(s) => OscType::String(s.clone()),
+        }
+    }
+
+    // Sent when a pulse ends and no release value is given
+    fn opposite(&self) -> ScheduleValue {
+        match self {
+            ScheduleValue::Bool(b) => ScheduleValue::Bool(!b),
+            ScheduleValue::Int(_) => ScheduleValue::Int(0),
+            ScheduleValue::Float(_) => ScheduleValue::Float(0.0),
+            ScheduleValue::String(_) => ScheduleValue::String(String::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    pub address: String,
+    // "minute hour day-of-month month day-of-week", e.g. "0 7 * * 1-5"
+    pub cron: String,
+    pub value: ScheduleValue,
+    // Sends the release value this many seconds after firing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<ScheduleValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub entry: ScheduleEntry,
+    cron: Cron,
+    zone: Zone,
+}
+
+impl Schedule {
+    pub fn new(entry: ScheduleEntry) -> Result<Schedule, String> {
+        let cron = Cron::parse(&entry.cron)?;
+        let zone = match &entry.timezone {
+            Some(timezone) => Zone::parse(timezone).ok_or(format!("unknown time zone {}", timezone))?,
+            None => Zone::Local,
+        };
+        Ok(Schedule { entry, cron, zone })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields, found {}", fields.len()));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAY_NAMES)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &[])?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    fn matches(&self, dt: &DateTime<FixedOffset>) -> bool {
+        let bit = |mask: u64, n: u32| mask & (1 << n) != 0;
+        let day = bit(self.days, dt.day());
+        let weekday = bit(self.weekdays, dt.weekday().num_days_from_sunday());
+        // Like cron, a restricted day-of-month and day-of-week match either one
+        let day_matches = if self.any_day || self.any_weekday { day && weekday } else { day || weekday };
+        bit(self.minutes, dt.minute()) &&
+            bit(self.hours, dt.hour()) &&
+            bit(self.months, dt.month()) &&
+            day_matches
+    }
+}
+
+fn parse_value(value: &str, names: &[&str]) -> Result<u32, String> {
+    let lower = value.to_ascii_lowercase();
+    if let Some(index) = names.iter().position(|name| *name == lower) {
+        return Ok(index as u32);
+    }
+    value.parse::<u32>().map_err(|_| format!("invalid value {}", value))
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse::<u32>().map_err(|_| format!("invalid step {}", step))?;
+                if step == 0 {
+                    return Err("step must not be 0".to_string());
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some((from, to)) = range.split_once('-') {
+            (parse_value(from, names)?, parse_value(to, names)?)
+        } else {
+            let value = parse_value(range, names)?;
+            // "5/15" means from 5 to the end in steps of 15
+            (value, if part.contains('/') { max } else { value })
+        };
+        if from < min || to > max || from > to {
+            return Err(format!("{} is out of range {}-{}", part, min, max));
+        }
+        for n in (from..=to).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+// Fires schedules on each minute boundary the clock passes
+pub struct Scheduler {
+    clock: Arc<dyn Clock>,
+    last: Option<DateTime<Utc>>,
+    releases: Vec<(DateTime<Utc>, OscMessage)>,
+    // Clock generation at the last poll
+    generation: u64,
+}
+
+impl Scheduler {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            generation: clock.generation(),
+            clock,
+            last: None,
+            releases: Vec::new(),
+        }
+    }
+
+    // Moves past the minutes without firing anything, but still sends the releases that are due
+    pub fn skip(&mut self, now: DateTime<Utc>) -> Vec<OscMessage> {
+        self.poll(now, &[])
+    }
+
+    pub fn poll(&mut self, now: DateTime<Utc>, schedules: &[Schedule]) -> Vec<OscMessage> {
+        let mut messages = Vec::new();
+        let generation = self.clock.generation();
+
+        // The clock jumped, so the minutes in between were never lived through, and the current one
+        // may already have fired. Pulses are ended at once, as their release times belong to the old time
+        if generation != self.generation {
+            self.generation = generation;
+            self.last = Some(now);
+            messages.extend(self.flush());
+        }
+
+        // The first poll also checks the minute it falls in
+        let last = self.last.unwrap_or_else(|| floor_minute(now) - Duration::minutes(1));
+        if last < now {
+            let mut minute = floor_minute(last);
+            for _ in 0..MAX_CATCH_UP_MINUTES {
+                minute += Duration::minutes(1);
+                if minute > now {
+                    break;
+                }
+                for schedule in schedules {
+                    if schedule.cron.matches(&schedule.zone.convert(minute)) {
+                        messages.push(self.fire(schedule, minute));
+                    }
+                }
+            }
+        }
+        self.last = Some(now);
+
+        let (due, pending): (Vec<_>, Vec<_>) = self.releases
+            .drain(..)
+            .partition(|(at, _)| *at <= now);
+        self.releases = pending;
+        messages.extend(due.into_iter().map(|(_, message)| message));
+
+        messages
+    }
+
+    // Releases that have not been sent yet, so pulses are not left on when osc_clock stops
+    pub fn flush(&mut self) -> Vec<OscMessage> {
+        self.releases.drain(..).map(|(_, message)| message).collect()
+    }
+
+    fn fire(&mut self, schedule: &Schedule, at: DateTime<Utc>) -> OscMessage {
+        let entry = &schedule.entry;
+        print_flush(
+            print_log(
+                t!("schedule_triggered", address = &entry.address, cron = &entry.cron),
+                LogType::EVENT
+            )
+        );
+        if let Some(duration) = entry.duration_seconds {
+            let release = entry.release.clone().unwrap_or_else(|| entry.value.opposite());
+            self.releases.push((
+                at + Duration::seconds(duration as i64),
+                make_message(&entry.address, vec![release.to_osc()]),
+            ));
+        }
+        make_message(&entry.address, vec![entry.value.to_osc()])
+    }
+}
+
+fn floor_minute(dt: DateTime<Utc>) -> DateTime<Utc> {
+    dt.with_second(0).and_then(|m| m.with_nanosecond(0)).unwrap_or(dt)
+}
+
+pub static SCHEDULES: Lazy<Mutex<Vec<Schedule>>> = Lazy::new(|| Mutex::new(load_schedules()));
+
+pub fn init_schedules() {
+    Lazy::force(&SCHEDULES);
+}
+
+// Schedules read from every orders/schedule_*.json, and the entries that could not be read
+fn read_schedule_files() -> (Vec<Schedule>, Vec<String>) {
+    let mut schedules = Vec::new();
+    let mut errors = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(Path::new("orders")) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_schedule = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|fname| fname.starts_with("schedule_") && fname.ends_with(".json"));
+            if !is_schedule {
+                continue;
+            }
+            let file_entries = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| serde_json::from_str::<Vec<ScheduleEntry>>(&data).map_err(|e| e.to_string()));
+            match file_entries {
+                Ok(file_entries) => {
+                    for entry in file_entries {
+                        let address = entry.address.clone();
+                        match Schedule::new(entry) {
+                            Ok(schedule) => schedules.push(schedule),
+                            Err(error) => errors.push(t!("invalid_schedule", address = address, error = error)),
+                        }
+                    }
+                }
+                Err(error) => {
+                    errors.push(t!("invalid_schedule", address = path.display(), error = error));
+                }
+            }
+        }
+    }
+
+    (schedules, errors)
+}
+
+// Schedules are optional, so nothing is created when missing
+pub fn load_schedules() -> Vec<Schedule> {
+    let (schedules, errors) = read_schedule_files();
+    for error in errors {
+        print_flush(print_log(error, LogType::WARN));
+    }
+    schedules
+}
+
+// Reads the schedules again. Fails when any entry is invalid
+pub fn reload_schedules() -> Result<Vec<Schedule>, String> {
+    let (schedules, errors) = read_schedule_files();
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(schedules),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ FixedClock, OffsetClock };
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    // Polls are given their time, so the clock only tells the scheduler about jumps
+    fn clock() -> Arc<dyn Clock> {
+        Arc::new(FixedClock::new(at("2024-06-14T00:00:00Z")))
+    }
+
+    fn schedule(cron: &str, duration_seconds: Option<u64>) -> Schedule {
+        Schedule::new(ScheduleEntry {
+            address: "/avatar/parameters/Alarm".to_string(),
+            cron: cron.to_string(),
+            value: ScheduleValue::Bool(true),
+            duration_seconds,
+            release: None,
+            timezone: Some("UTC".to_string()),
+        }).unwrap()
+    }
+
+    #[test]
+    fn cron_fields() {
+        let cron = Cron::parse("*/15 7,19 * * mon-fri").unwrap();
+        assert_eq!(cron.minutes, (1 << 0) | (1 << 15) | (1 << 30) | (1 << 45));
+        assert_eq!(cron.hours, (1 << 7) | (1 << 19));
+        assert_eq!(cron.weekdays, 0b0111110);
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays & 1, 1);
+        assert!(Cron::parse("0 7 * *").is_err());
+        assert!(Cron::parse("60 7 * * *").is_err());
+        assert!(Cron::parse("0 7 * * */0").is_err());
+    }
+
+    #[test]
+    fn weekdays_only() {
+        let cron = Cron::parse("0 7 * * 1-5").unwrap();
+        // 2024-06-14 is a Friday
+        assert!(cron.matches(&at("2024-06-14T07:00:00Z").fixed_offset()));
+        assert!(!cron.matches(&at("2024-06-15T07:00:00Z").fixed_offset()));
+        assert!(!cron.matches(&at("2024-06-14T07:01:00Z").fixed_offset()));
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        let cron = Cron::parse("0 12 1 * sun").unwrap();
+        assert!(cron.matches(&at("2024-06-01T12:00:00Z").fixed_offset()));
+        assert!(cron.matches(&at("2024-06-02T12:00:00Z").fixed_offset()));
+        assert!(!cron.matches(&at("2024-06-03T12:00:00Z").fixed_offset()));
+    }
+
+    #[test]
+    fn pulse_is_released_after_duration() {
+        let schedules = vec![schedule("0 7 * * *", Some(5))];
+        let mut scheduler = Scheduler::new(clock());
+
+        assert!(scheduler.poll(at("2024-06-14T06:59:59Z"), &schedules).is_empty());
+        let fired = scheduler.poll(at("2024-06-14T07:00:00Z"), &schedules);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].args, vec![OscType::Bool(true)]);
+
+        assert!(scheduler.poll(at("2024-06-14T07:00:04Z"), &schedules).is_empty());
+        let released = scheduler.poll(at("2024-06-14T07:00:05Z"), &schedules);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].args, vec![OscType::Bool(false)]);
+    }
+
+    #[test]
+    fn skipped_ticks_still_fire() {
+        let schedules = vec![schedule("0 7 * * *", None)];
+        let mut scheduler = Scheduler::new(clock());
+
+        scheduler.poll(at("2024-06-14T06:59:59Z"), &schedules);
+        assert_eq!(scheduler.poll(at("2024-06-14T07:00:02Z"), &schedules).len(), 1);
+        assert!(scheduler.poll(at("2024-06-14T07:00:03Z"), &schedules).is_empty());
+    }
+
+    #[test]
+    fn first_poll_includes_its_minute() {
+        let schedules = vec![schedule("0 7 * * *", None)];
+        let mut scheduler = Scheduler::new(clock());
+
+        assert_eq!(scheduler.poll(at("2024-06-14T07:00:00.500Z"), &schedules).len(), 1);
+        assert!(scheduler.poll(at("2024-06-14T07:00:01Z"), &schedules).is_empty());
+    }
+
+    #[test]
+    fn clock_jumps_do_not_catch_up() {
+        let schedules = vec![schedule("* * * * *", Some(60))];
+        let clock = Arc::new(OffsetClock::new(clock(), Duration::zero()));
+        let mut scheduler = Scheduler::new(clock.clone());
+
+        assert_eq!(scheduler.poll(at("2024-06-14T07:00:00Z"), &schedules).len(), 1);
+        // An hour ahead fires nothing, and releases the pulse from before
+        clock.set_offset(Duration::hours(1));
+        let jumped = scheduler.poll(at("2024-06-14T08:00:30Z"), &schedules);
+        assert_eq!(jumped.len(), 1);
+        assert_eq!(jumped[0].args, vec![OscType::Bool(false)]);
+        assert_eq!(scheduler.poll(at("2024-06-14T08:01:00Z"), &schedules).len(), 1);
+    }
+
+    #[test]
+    fn skip_sends_only_releases() {
+        let schedules = vec![schedule("0 7 * * *", Some(5))];
+        let mut scheduler = Scheduler::new(clock());
+
+        scheduler.poll(at("2024-06-14T07:00:00Z"), &schedules);
+        let released = scheduler.skip(at("2024-06-14T07:00:05Z"));
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].args, vec![OscType::Bool(false)]);
+        assert!(scheduler.skip(at("2024-06-15T07:00:05Z")).is_empty());
+        assert!(scheduler.poll(at("2024-06-15T07:00:06Z"), &schedules).is_empty());
+    }
+
+    #[test]
+    fn flush_returns_pending_releases() {
+        let schedules = vec![schedule("0 7 * * *", Some(60))];
+        let mut scheduler = Scheduler::new(clock());
+
+        scheduler.poll(at("2024-06-14T07:00:00Z"), &schedules);
+        let released = scheduler.flush();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].args, vec![OscType::Bool(false)]);
+        assert!(scheduler.poll(at("2024-06-14T07:01:00Z"), &schedules).is_empty());
+    }
+}
@@ -1,95 +1,267 @@
-use chrono::{ Local, Timelike, Datelike };
-use vrchat_osc::rosc::{ OscPacket, OscMessage };
-use std::net::{ UdpSocket, SocketAddr };
+use chrono::{ Duration, Local };
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+use vrchat_osc::rosc::{ OscPacket, OscMessage, OscType };
 
+use crate::bundle::{ self, packets };
+use crate::cache::ValueCache;
+use crate::clock::Clock;
+use crate::control;
 use crate::log::{ print_log, print_flush, LogType };
-use crate::config::{ CONFIG };
-use crate::order::ORDERS;
-use std::thread;
-use crate::message::{ build, BuilderParams, SyncFlag };
+use crate::config::{ Config, Destination, CONFIG, DEFAULT_DESTINATION, MIN_TICK_INTERVAL_MS };
+use crate::order::{ Order, ORDERS };
+use crate::supervisor;
+use crate::schedule::{ Scheduler, SCHEDULES };
+use crate::ticker::Ticker;
+use crate::transform;
+use crate::transport::{ self, SendError };
+use crate::unit::UnitType;
+use crate::message::{ build, BuilderParams };
 
-pub async fn sender<F, Fut>(s: F)
-    where F: Fn(OscMessage) -> Fut, Fut: std::future::Future<Output = ()>
+// `s` sends a packet and returns whether it went out. Runs until `stopping` changes to true
+pub async fn sender<F, Fut>(s: F, mut stopping: watch::Receiver<bool>, clock: Arc<dyn Clock>)
+    where F: Fn(OscPacket, Destination) -> Fut, Fut: std::future::Future<Output = bool>
 {
     let mut config = CONFIG.lock().unwrap().clone();
 
-    if !config.use_osc_query {
+    for destination in config.destinations() {
+        // With OSCQuery, the default destination is VRChat found on the network
+        if supervisor::is_osc_query() && destination.name == DEFAULT_DESTINATION {
+            continue;
+        }
         print_flush(
             print_log(
                 t!(
                     "sending_to_N",
-                    address = format!("{}:{}", config.sender_ip, config.sender_port)
+                    address = format!("{}:{}", destination.host, destination.port)
                 ),
                 LogType::INFO
             )
         );
     }
 
-    let mut dt = Local::now();
-
-    let mut current_second = dt.second();
-    let mut current_minute = u32::MAX;
-    let mut current_hour = u32::MAX;
-    let mut current_day = u32::MAX;
-
-    while dt.second() == current_second {
-        thread::sleep(std::time::Duration::from_millis(10));
-        dt = Local::now();
+    if !clock.is_real() {
+        print_flush(
+            print_log(
+                t!(
+                    "simulating_time",
+                    timestamp = clock.now().with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                    speed = clock.rate()
+                ),
+                LogType::INFO
+            )
+        );
     }
 
+    // What each destination has, so one that is added or comes back gets every value
+    let mut caches: HashMap<Destination, ValueCache> = HashMap::new();
+    let mut scheduler = Scheduler::new(clock.clone());
+    let mut ticker = Ticker::new(tick_interval(&config), clock.clone());
+
     loop {
+        // Stops between ticks, so a batch of values is never cut short
+        let dt = tokio::select! {
+            dt = ticker.tick() => dt,
+            _ = stopping.wait_for(|stopping| *stopping) => break,
+        };
         config = CONFIG.lock().unwrap().clone();
+        if ticker.interval() != tick_interval(&config) {
+            ticker = Ticker::new(tick_interval(&config), clock.clone());
+        }
+
+        let orders = ORDERS.lock().unwrap().clone().sender;
 
-        while config.restrict_send_rate && dt.second() == current_second {
-            thread::sleep(std::time::Duration::from_millis(config.check_rate_ms));
-            dt = Local::now();
+        // Nothing is built while paused, so changes made meanwhile are sent on resume.
+        // Schedules are skipped rather than caught up, but pulses already on are still released
+        if control::is_paused() {
+            let releases = scheduler.skip(dt);
+            send_with(&s, &releases, &orders, &config).await;
+            continue;
         }
 
-        let mut flag = SyncFlag::MINUTE | SyncFlag::HOUR | SyncFlag::DAY;
-
-        if !config.send_all_value_every_time {
-            if dt.minute() == current_minute {
-                flag &= !SyncFlag::MINUTE;
-            } else {
-                current_minute = dt.minute();
-            }
-            if dt.hour() == current_hour {
-                flag &= !SyncFlag::HOUR;
-            } else {
-                current_hour = dt.hour();
-            }
-            if dt.day() == current_day {
-                flag &= !SyncFlag::DAY;
-            } else {
-                current_day = dt.day();
-            }
+        let schedule_messages = scheduler.poll(dt, &SCHEDULES.lock().unwrap());
+        let addresses: Vec<&str> = orders.iter().map(|order| order.address.as_str()).collect();
+
+        // Every value is built, and each destination's cache drops the ones it already has
+        let built = build(BuilderParams {
+            orders: &orders,
+            now: dt,
+        });
+        let destinations = config.destinations();
+        caches.retain(|destination, _| destinations.contains(destination));
+        for destination in destinations {
+            let cache = caches.entry(destination.clone()).or_insert_with(ValueCache::new);
+            cache.retain(&addresses);
+            let mut messages: Vec<OscMessage> = built
+                .iter()
+                .filter(|(order, message)| {
+                    order.sends_to(&destination.name) && cache.should_send(message, keepalive(order, &config), dt)
+                })
+                .map(|(_, message)| message.clone())
+                .collect();
+            messages.append(&mut for_destination(&schedule_messages, &orders, &destination.name));
+            let failed = send_to(&s, messages, &destination, &config).await;
+            // Values that did not go out are sent again on the next tick, whether they changed or not
+            cache.forget(&failed);
         }
-        let messages = build(BuilderParams {
-            orders: ORDERS.clone().sender,
-            sync_flag: flag,
+    }
+
+    // Ends the pulses still on, and lets avatars show that the clock is offline
+    let config = CONFIG.lock().unwrap().clone();
+    let orders = ORDERS.lock().unwrap().clone().sender;
+    let mut messages = scheduler.flush();
+    messages.extend(offline_messages(&orders, &config));
+    send_with(&s, &messages, &orders, &config).await;
+}
+
+// online orders turn false, through their transforms like any other value, then the offline parameters
+fn offline_messages(orders: &[Order], config: &Config) -> Vec<OscMessage> {
+    let online = orders
+        .iter()
+        .filter(|order| order.r#type == UnitType::Online)
+        .map(|order| OscMessage {
+            addr: order.address.clone(),
+            args: vec![transform::apply(OscType::Bool(false), order.transforms.as_deref().unwrap_or_default())],
         });
-        for message in messages {
-            s(message).await;
+    let parameters = config.offline_parameters.iter().map(|parameter| OscMessage {
+        addr: parameter.address.clone(),
+        args: vec![parameter.value.to_osc()],
+    });
+    online.chain(parameters).collect()
+}
+
+async fn send_with<F, Fut>(s: &F, messages: &[OscMessage], orders: &[Order], config: &Config)
+    where F: Fn(OscPacket, Destination) -> Fut, Fut: std::future::Future<Output = bool>
+{
+    for destination in config.destinations() {
+        send_to(s, for_destination(messages, orders, &destination.name), &destination, config).await;
+    }
+}
+
+// Returns the addresses of the messages that could not be sent
+async fn send_to<F, Fut>(s: &F, messages: Vec<OscMessage>, destination: &Destination, config: &Config) -> Vec<String>
+    where F: Fn(OscPacket, Destination) -> Fut, Fut: std::future::Future<Output = bool>
+{
+    let mut failed = Vec::new();
+    // Values of one tick are applied together when bundled
+    for packet in packets(messages, config) {
+        let addresses = bundle::addresses(&packet);
+        if !s(packet, destination.clone()).await {
+            failed.extend(addresses);
         }
-        current_second = dt.second();
     }
+    failed
 }
 
-pub fn send(message: OscMessage, ip: &str, port: u16) {
-    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    let addr = SocketAddr::new(ip.parse().unwrap(), port);
+fn tick_interval(config: &Config) -> Duration {
+    Duration::milliseconds(config.tick_interval_ms.max(MIN_TICK_INTERVAL_MS) as i64)
+}
 
-    let packet = OscPacket::Message(message);
-    let encoded_packet = vrchat_osc::rosc::encoder::encode(&packet).unwrap();
+// The order's own keepalive, or the one from config.json. Zero turns it off
+fn keepalive(order: &Order, config: &Config) -> Option<Duration> {
+    let seconds = order.keepalive_seconds.unwrap_or(config.keepalive_seconds);
+    if seconds == 0 {
+        return None;
+    }
+    Some(Duration::seconds(seconds as i64))
+}
+
+// Messages of the orders sending to the destination, and those no order owns (schedules)
+fn for_destination(messages: &[OscMessage], orders: &[Order], destination: &str) -> Vec<OscMessage> {
+    messages
+        .iter()
+        .filter(|message| {
+            let mut owners = orders.iter().filter(|order| order.address == message.addr).peekable();
+            owners.peek().is_none() || owners.any(|order| order.sends_to(destination))
+        })
+        .cloned()
+        .collect()
+}
 
-    socket.send_to(&encoded_packet, addr).unwrap();
+// Sends a batch of messages to every destination that takes them, once
+pub async fn send_all(messages: Vec<OscMessage>, orders: &[Order], config: &Config) {
+    for destination in config.destinations() {
+        for packet in packets(for_destination(&messages, orders, &destination.name), config) {
+            // Failures are logged by the transport, and sending carries on with the next packet
+            let _ = send(packet, &destination).await;
+        }
+    }
+}
+
+pub async fn send(packet: OscPacket, destination: &Destination) -> Result<(), SendError> {
+    transport::send(&packet, destination).await?;
 
     if CONFIG.lock().unwrap().show_debug_log {
         let str = t!(
             "debug_on_send_message",
-            address = format!("{}:{}", ip, port),
+            address = format!("{}:{}", destination.host, destination.port),
             timestamp = Local::now().format("%Y-%m-%d %H:%M:%S.%f")
         );
         print_flush(print_log(str, LogType::SEND));
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::{ CastType, Transform };
+    use crate::unit::UnitType;
+    use vrchat_osc::rosc::OscType;
+
+    fn message(address: &str) -> OscMessage {
+        OscMessage {
+            addr: address.to_string(),
+            args: vec![OscType::Int(0)],
+        }
+    }
+
+    fn addresses(messages: Vec<OscMessage>) -> Vec<String> {
+        messages.into_iter().map(|message| message.addr).collect()
+    }
+
+    #[test]
+    fn routes_messages_by_order_destinations() {
+        let mut overlay_only = Order::new(UnitType::SecondInt, "/overlay/second");
+        overlay_only.destinations = Some(vec!["overlay".to_string()]);
+        let everywhere = Order::new(UnitType::MinuteInt, "/avatar/parameters/minute");
+        let orders = vec![overlay_only, everywhere];
+        let messages = vec![message("/overlay/second"), message("/avatar/parameters/minute"), message("/alarm")];
+
+        assert_eq!(
+            addresses(for_destination(&messages, &orders, DEFAULT_DESTINATION)),
+            vec!["/avatar/parameters/minute", "/alarm"]
+        );
+        assert_eq!(
+            addresses(for_destination(&messages, &orders, "overlay")),
+            vec!["/overlay/second", "/avatar/parameters/minute", "/alarm"]
+        );
+    }
+
+    #[test]
+    fn offline_values_are_transformed() {
+        let mut online = Order::new(UnitType::Online, "/avatar/parameters/online");
+        online.transforms = Some(vec![Transform::Cast(CastType::Int), Transform::Offset(5.0)]);
+        let orders = vec![online, Order::new(UnitType::Online, "/avatar/parameters/plain")];
+        let messages = offline_messages(&orders, &Config::default());
+
+        assert_eq!(messages[0].args, vec![OscType::Int(5)]);
+        assert_eq!(messages[1].args, vec![OscType::Bool(false)]);
+    }
+
+    #[test]
+    fn default_destination_can_be_replaced() {
+        let mut config = Config::default();
+        assert_eq!(config.destinations().len(), 1);
+        config.destinations.push(Destination {
+            name: DEFAULT_DESTINATION.to_string(),
+            host: "192.168.0.10".to_string(),
+            port: 9000,
+            protocol: crate::config::Protocol::Udp,
+        });
+        let destinations = config.destinations();
+        assert_eq!(destinations.len(), 1);
+        assert_eq!(destinations[0].host, "192.168.0.10");
+    }
 }
@@ -0,0 +1,238 @@
+use chrono::{ DateTime, Local };
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use wildmatch::WildMatch;
+
+use crate::log::{ print_flush, print_log, LogType };
+
+// How often the OSC Query services are asked whether they are still there
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+// mDNS does not report services that go away, so one that has not answered for this long is forgotten
+const SERVICE_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ServiceKind {
+    Osc,
+    OscQuery,
+}
+
+impl fmt::Display for ServiceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceKind::Osc => write!(f, "OSC"),
+            ServiceKind::OscQuery => write!(f, "OSC Query"),
+        }
+    }
+}
+
+// A service announced on the network, such as a VRChat client or another osc_clock
+#[derive(Debug, Clone)]
+pub struct Service {
+    // Instance name, without the "._osc._udp.local." part
+    pub name: String,
+    pub kind: ServiceKind,
+    pub address: SocketAddr,
+    pub last_seen: DateTime<Local>,
+}
+
+type Services = BTreeMap<(String, ServiceKind), Service>;
+
+static SERVICES: Lazy<Mutex<Services>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+// "VRChat-Client-AB12CD._oscjson._tcp.local." -> "VRChat-Client-AB12CD"
+pub fn instance_name(full_name: &str) -> &str {
+    [ "._osc._udp.local.", "._oscjson._tcp.local." ]
+        .iter()
+        .find_map(|suffix| full_name.strip_suffix(suffix))
+        .unwrap_or(full_name)
+}
+
+// Records a service reported by mDNS and returns its instance name
+pub fn discovered(kind: ServiceKind, full_name: &str, address: SocketAddr) -> String {
+    let name = instance_name(full_name).to_string();
+    SERVICES.lock().unwrap().insert((name.clone(), kind), Service {
+        name: name.clone(),
+        kind,
+        address,
+        last_seen: Local::now(),
+    });
+    name
+}
+
+pub fn list() -> Vec<Service> {
+    SERVICES.lock().unwrap().values().cloned().collect()
+}
+
+// Whether an OSC Query service selected by osc_query_target is still there
+pub fn has_target(target: &str) -> bool {
+    list()
+        .iter()
+        .any(|service| service.kind == ServiceKind::OscQuery && is_target(target, &service.name))
+}
+
+// Connects to the OSC Query server of every service, and forgets the ones that stopped answering
+pub async fn watch() {
+    loop {
+        tokio::time::sleep(PROBE_INTERVAL).await;
+        let servers: Vec<Service> = list()
+            .into_iter()
+            .filter(|service| service.kind == ServiceKind::OscQuery)
+            .collect();
+        for server in servers {
+            let connect = tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(server.address)).await;
+            if let Ok(Ok(_)) = connect {
+                seen(&mut SERVICES.lock().unwrap(), &server.name, Local::now());
+            }
+        }
+        let lost = expire(&mut SERVICES.lock().unwrap(), Local::now());
+        for service in lost {
+            print_flush(
+                print_log(
+                    t!("service_lost", name = service.name, kind = service.kind, address = service.address),
+                    LogType::INFO
+                )
+            );
+        }
+    }
+}
+
+fn seen(services: &mut Services, name: &str, now: DateTime<Local>) {
+    for service in services.values_mut().filter(|service| service.name == name) {
+        service.last_seen = now;
+    }
+}
+
+// Removes the services whose OSC Query server has not answered within SERVICE_TIMEOUT.
+// Services announced without an OSC Query server cannot be checked, so they are kept
+fn expire(services: &mut Services, now: DateTime<Local>) -> Vec<Service> {
+    let timeout = chrono::Duration::from_std(SERVICE_TIMEOUT).unwrap();
+    let lost: Vec<String> = services
+        .values()
+        .filter(|service| service.kind == ServiceKind::OscQuery && now - service.last_seen > timeout)
+        .map(|service| service.name.clone())
+        .collect();
+    let (removed, kept): (Services, Services) = std::mem::take(services)
+        .into_iter()
+        .partition(|((name, _), _)| lost.contains(name));
+    *services = kept;
+    removed.into_values().collect()
+}
+
+// Whether an instance name is selected by osc_query_target, which takes * and ?
+pub fn is_target(target: &str, name: &str) -> bool {
+    WildMatch::new(target).matches(name)
+}
+
+// mDNS names are lowercase, with anything but letters, digits and - replaced by -
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if (c.is_ascii() && !c.is_ascii_alphanumeric()) || c.is_control() {
+                '-'
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+// The name itself, or with -2, -3, ... if another instance on the network already uses it.
+// Only the services found so far are known, so two instances starting together can still pick the same name
+pub fn unique_name(name: &str) -> String {
+    let taken: Vec<String> = list()
+        .iter()
+        .map(|service| sanitize(&service.name))
+        .collect();
+    unique_among(name, &taken)
+}
+
+fn unique_among(name: &str, taken: &[String]) -> String {
+    (1..)
+        .map(|n| if n == 1 { name.to_string() } else { format!("{}-{}", name, n) })
+        .find(|candidate| !taken.contains(&sanitize(candidate)))
+        .unwrap()
+}
+
+pub fn print_table(target: &str) {
+    let services = list();
+    let mut table = t!("discovered_services", count = services.len()).to_string();
+    for service in services {
+        // Services the clock is sent to are marked with *
+        let marker = if service.kind == ServiceKind::Osc && is_target(target, &service.name) { "*" } else { " " };
+        table.push_str(
+            &format!(
+                "\n\t{} {} ({}) {} {}",
+                marker,
+                service.name,
+                service.kind,
+                service.address,
+                service.last_seen.format("%H:%M:%S")
+            )
+        );
+    }
+    print_flush(print_log(table, LogType::INFO));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_service_types() {
+        assert_eq!(instance_name("VRChat-Client-AB12CD._oscjson._tcp.local."), "VRChat-Client-AB12CD");
+        assert_eq!(instance_name("osc-clock._osc._udp.local."), "osc-clock");
+        assert_eq!(instance_name("other"), "other");
+    }
+
+    #[test]
+    fn selects_targets() {
+        assert!(is_target("VRChat-Client-*", "VRChat-Client-AB12CD"));
+        assert!(is_target("VRChat-Client-AB12CD", "VRChat-Client-AB12CD"));
+        assert!(!is_target("VRChat-Client-AB12CD", "VRChat-Client-EF34GH"));
+        assert!(!is_target("VRChat-Client-*", "osc-clock"));
+    }
+
+    fn service(name: &str, kind: ServiceKind, last_seen: DateTime<Local>) -> ((String, ServiceKind), Service) {
+        ((name.to_string(), kind), Service {
+            name: name.to_string(),
+            kind,
+            address: "127.0.0.1:9000".parse().unwrap(),
+            last_seen,
+        })
+    }
+
+    #[test]
+    fn forgets_services_that_stopped_answering() {
+        let now = Local::now();
+        let old = now - chrono::Duration::seconds(60);
+        let mut services: Services = [
+            service("VRChat-Client-AB12CD", ServiceKind::Osc, old),
+            service("VRChat-Client-AB12CD", ServiceKind::OscQuery, old),
+            service("VRChat-Client-EF34GH", ServiceKind::Osc, old),
+            service("VRChat-Client-EF34GH", ServiceKind::OscQuery, old),
+            service("osc-only", ServiceKind::Osc, old),
+        ].into_iter().collect();
+
+        seen(&mut services, "VRChat-Client-EF34GH", now);
+        let lost = expire(&mut services, now);
+        assert_eq!(lost.len(), 2);
+        assert!(lost.iter().all(|service| service.name == "VRChat-Client-AB12CD"));
+        let names: Vec<&str> = services.keys().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["VRChat-Client-EF34GH", "VRChat-Client-EF34GH", "osc-only"]);
+    }
+
+    #[test]
+    fn picks_unused_names() {
+        assert_eq!(unique_among("osc-clock", &[]), "osc-clock");
+        let taken = ["osc-clock".to_string(), "osc-clock-2".to_string()];
+        assert_eq!(unique_among("osc-clock", &taken), "osc-clock-3");
+        // Compared the way mDNS stores them
+        assert_eq!(unique_among("OSC Clock", &["osc-clock".to_string()]), "OSC Clock-2");
+    }
+}
@@ -0,0 +1,62 @@
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::log::{ print_flush, print_log, LogType };
+
+// How long the tasks get to finish, sending the offline parameters included
+const GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+// Tasks that are stopped together, such as the ones of a supervisor mode
+#[derive(Default)]
+pub struct Tasks {
+    stopping: watch::Sender<bool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Tasks {
+    // The task gets a receiver that changes to true when it should stop
+    pub fn spawn<F, Fut>(&mut self, task: F)
+        where F: FnOnce(watch::Receiver<bool>) -> Fut, Fut: Future<Output = ()> + Send + 'static
+    {
+        self.handles.push(tokio::spawn(task(self.stopping.subscribe())));
+    }
+
+    // Lets the tasks finish what they are sending, and aborts the ones that take too long
+    pub async fn stop(&mut self) {
+        self.stopping.send_replace(true);
+
+        let deadline = Instant::now() + GRACE_PERIOD;
+        for task in self.handles.drain(..) {
+            let abort_handle = task.abort_handle();
+            if tokio::time::timeout_at(deadline, task).await.is_err() {
+                abort_handle.abort();
+            }
+        }
+    }
+}
+
+// Waits for Ctrl+C, or SIGTERM on Unix
+pub async fn signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
+
+// Stops the running tasks before osc_clock exits
+pub async fn stop(tasks: &mut Tasks) {
+    print_flush(print_log(t!("shutting_down").to_string(), LogType::INFO));
+    tasks.stop().await;
+}
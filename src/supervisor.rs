@@ -0,0 +1,160 @@
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use vrchat_osc::{ Error, VRChatOSC };
+
+use crate::clock::OffsetClock;
+use crate::config::CONFIG;
+use crate::legacy;
+use crate::log::{ print_flush, print_log, LogType };
+use crate::osc_query;
+use crate::services;
+use crate::shutdown::{ self, Tasks };
+
+// How long VRChat is looked for with OSCQuery before falling back to plain OSC
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+// How often OSCQuery is started again after it failed to start
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+static OSC_QUERY_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+// Whether values are currently sent with OSCQuery rather than to sender_ip and sender_port
+pub fn is_osc_query() -> bool {
+    OSC_QUERY_ACTIVE.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    OscQuery,
+    Legacy,
+}
+
+// Uses OSCQuery while VRChat can be found with it, and plain OSC otherwise
+struct Supervisor {
+    clock: Arc<OffsetClock>,
+    vrchat_osc: Option<Arc<VRChatOSC>>,
+    found: Arc<Notify>,
+    mode: Option<Mode>,
+    tasks: Tasks,
+}
+
+impl Supervisor {
+    fn new(clock: Arc<OffsetClock>) -> Self {
+        Self {
+            clock,
+            vrchat_osc: None,
+            found: Arc::new(Notify::new()),
+            mode: None,
+            tasks: Tasks::default(),
+        }
+    }
+
+    async fn run(&mut self) {
+        loop {
+            if self.vrchat_osc.is_none() {
+                match osc_query::connect(self.found.clone(), self.clock.clone()).await {
+                    Ok(vrchat_osc) => {
+                        self.vrchat_osc = Some(vrchat_osc);
+                    }
+                    Err(error) => {
+                        print_flush(
+                            print_log(t!("osc_query_failed", error = format!("{:?}", error)), LogType::WARN)
+                        );
+                    }
+                }
+            }
+
+            let found = match (&self.vrchat_osc, self.mode) {
+                // Plain OSC keeps running until VRChat shows up
+                (Some(_), Some(Mode::Legacy)) => {
+                    self.found.notified().await;
+                    true
+                }
+                (Some(_), _) => {
+                    let found = tokio::time::timeout(DISCOVERY_TIMEOUT, self.found.notified()).await.is_ok();
+                    if !found {
+                        print_flush(
+                            print_log(
+                                t!(
+                                    "vrchat_not_found",
+                                    target = CONFIG.lock().unwrap().osc_query_target,
+                                    seconds = DISCOVERY_TIMEOUT.as_secs()
+                                ),
+                                LogType::WARN
+                            )
+                        );
+                    }
+                    found
+                }
+                (None, _) => false,
+            };
+
+            let clock = self.clock.clone();
+            match self.vrchat_osc.clone() {
+                Some(vrchat_osc) if found => {
+                    self.switch(Mode::OscQuery, || osc_query::spawn_sender(vrchat_osc, clock.clone())).await;
+                    // Services that stop answering are forgotten, so VRChat is gone once none is left
+                    while services::has_target(&CONFIG.lock().unwrap().osc_query_target) {
+                        tokio::time::sleep(services::PROBE_INTERVAL).await;
+                    }
+                    print_flush(
+                        print_log(
+                            t!("vrchat_lost", target = CONFIG.lock().unwrap().osc_query_target),
+                            LogType::WARN
+                        )
+                    );
+                    self.switch(Mode::Legacy, || legacy::spawn(clock)).await;
+                }
+                _ => {
+                    self.switch(Mode::Legacy, || legacy::spawn(clock)).await;
+                }
+            }
+
+            if self.vrchat_osc.is_none() {
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+
+    async fn switch(&mut self, mode: Mode, spawn: impl FnOnce() -> Tasks) {
+        if self.mode == Some(mode) {
+            return;
+        }
+        // The old tasks send their offline values, and free the receiver port, before the new ones start
+        self.tasks.stop().await;
+        OSC_QUERY_ACTIVE.store(mode == Mode::OscQuery, Ordering::SeqCst);
+        let config = CONFIG.lock().unwrap().clone();
+        let message = match mode {
+            Mode::OscQuery => t!("switched_to_osc_query", target = config.osc_query_target),
+            Mode::Legacy => {
+                t!(
+                    "fallback_to_legacy",
+                    address = format!("{}:{}", config.sender_ip, config.sender_port),
+                    target = config.osc_query_target
+                )
+            }
+        };
+        print_flush(print_log(message, LogType::EVENT));
+        self.mode = Some(mode);
+        self.tasks = spawn();
+    }
+
+    async fn stop(&mut self) -> Result<(), Error> {
+        shutdown::stop(&mut self.tasks).await;
+        if let Some(vrchat_osc) = self.vrchat_osc.take() {
+            vrchat_osc.shutdown().await?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn start(clock: Arc<OffsetClock>) -> Result<(), Error> {
+    tokio::spawn(services::watch());
+    let mut supervisor = Supervisor::new(clock);
+    tokio::select! {
+        _ = supervisor.run() => {}
+        result = shutdown::signal() => result?,
+    }
+    supervisor.stop().await
+}
@@ -0,0 +1,191 @@
+use chrono::{ DateTime, Duration, DurationRound, Local, Utc };
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use crate::clock::Clock;
+use crate::config::CONFIG;
+use crate::log::{ print_flush, print_log, LogType };
+
+// Shortest sleep, so waiting out the last microseconds to a boundary does not spin
+const MIN_SLEEP: StdDuration = StdDuration::from_millis(1);
+// Ticks summarized in each debug log line
+const STATS_WINDOW: u32 = 60;
+
+// Lateness of the ticks since the last report, in microseconds
+struct TickStats {
+    count: u32,
+    sum: i64,
+    min: i64,
+    max: i64,
+}
+
+impl TickStats {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            sum: 0,
+            min: i64::MAX,
+            max: i64::MIN,
+        }
+    }
+
+    fn record(&mut self, lateness: i64) {
+        self.count += 1;
+        self.sum += lateness;
+        self.min = self.min.min(lateness);
+        self.max = self.max.max(lateness);
+    }
+}
+
+// Sleeps until each boundary of the clock without blocking the runtime
+pub struct Ticker {
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    last: Option<DateTime<Utc>>,
+    stats: TickStats,
+    // Clock generation the last tick was taken from
+    generation: u64,
+}
+
+impl Ticker {
+    pub fn new(interval: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            generation: clock.generation(),
+            clock,
+            interval,
+            last: None,
+            stats: TickStats::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Returns the boundary instant, which is what values should be built for
+    pub async fn tick(&mut self) -> DateTime<Utc> {
+        // The clock jumped, so start again from the next boundary instead of replaying the jump
+        if self.clock.generation() != self.generation {
+            self.generation = self.clock.generation();
+            self.last = None;
+        }
+        let target = self.next_target(self.clock.now());
+
+        // Ticks run up to a timer resolution late, which is not worth waking up early and spinning for.
+        // The sleep is only repeated when the clock is still short of the boundary (simulated time)
+        let mut slept = false;
+        while self.clock.now() < target {
+            let deadline = tokio::time::Instant::now() + self.real_until(target).max(MIN_SLEEP);
+            tokio::time::sleep_until(deadline).await;
+            slept = true;
+        }
+        // A tick that is already due still lets the other tasks run, so a sender that falls behind does not hog the runtime
+        if !slept {
+            tokio::task::yield_now().await;
+        }
+
+        let lateness = self.clock.now().signed_duration_since(target);
+        self.report((lateness.num_microseconds().unwrap_or(i64::MAX) as f64 / self.clock.rate()) as i64);
+        self.last = Some(target);
+        target
+    }
+
+    // Boundaries are taken from the clock rather than added up, so late ticks do not drift
+    fn next_target(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let following = floor(now, self.interval) + self.interval;
+        match self.last {
+            // Boundaries that were missed are skipped, in simulated time as well, as it can run faster than sending
+            Some(last) => (last + self.interval).max(floor(now, self.interval)).min(following),
+            None => following,
+        }
+    }
+
+    // Real time left until the clock reaches `target`
+    fn real_until(&self, target: DateTime<Utc>) -> StdDuration {
+        target
+            .signed_duration_since(self.clock.now())
+            .to_std()
+            .unwrap_or_default()
+            .div_f64(self.clock.rate())
+    }
+
+    fn report(&mut self, lateness: i64) {
+        self.stats.record(lateness);
+        if self.stats.count < STATS_WINDOW {
+            return;
+        }
+        if CONFIG.lock().unwrap().show_debug_log {
+            let str = t!(
+                "debug_tick_lateness",
+                count = self.stats.count,
+                average = format!("{:.3}", (self.stats.sum as f64) / (self.stats.count as f64) / 1000.0),
+                min = format!("{:.3}", (self.stats.min as f64) / 1000.0),
+                max = format!("{:.3}", (self.stats.max as f64) / 1000.0),
+                timestamp = Local::now().format("%Y-%m-%d %H:%M:%S")
+            );
+            print_flush(print_log(str, LogType::INFO));
+        }
+        self.stats = TickStats::new();
+    }
+}
+
+fn floor(dt: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
+    dt.duration_trunc(interval).unwrap_or(dt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ AcceleratedClock, FixedClock };
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn ticker(interval: Duration, last: Option<&str>) -> Ticker {
+        let mut ticker = Ticker::new(interval, Arc::new(FixedClock::new(at("2024-06-14T12:00:00Z"))));
+        ticker.last = last.map(at);
+        ticker
+    }
+
+    #[test]
+    fn first_tick_is_the_next_boundary() {
+        let every_second = ticker(Duration::seconds(1), None);
+        assert_eq!(every_second.next_target(at("2024-06-14T12:00:00.300Z")), at("2024-06-14T12:00:01Z"));
+        assert_eq!(every_second.next_target(at("2024-06-14T12:00:01Z")), at("2024-06-14T12:00:02Z"));
+        let fast = ticker(Duration::milliseconds(100), None);
+        assert_eq!(fast.next_target(at("2024-06-14T12:00:00.250Z")), at("2024-06-14T12:00:00.300Z"));
+    }
+
+    #[test]
+    fn late_ticks_do_not_drift() {
+        let ticker = ticker(Duration::seconds(1), Some("2024-06-14T12:00:01Z"));
+        // Woken early or late, the next tick stays on the boundary
+        assert_eq!(ticker.next_target(at("2024-06-14T12:00:01.004Z")), at("2024-06-14T12:00:02Z"));
+        assert_eq!(ticker.next_target(at("2024-06-14T12:00:01.999Z")), at("2024-06-14T12:00:02Z"));
+    }
+
+    #[test]
+    fn real_time_skips_missed_boundaries() {
+        let ticker = ticker(Duration::seconds(1), Some("2024-06-14T12:00:01Z"));
+        // After a stall the current boundary is sent at once, instead of every missed one
+        assert_eq!(ticker.next_target(at("2024-06-14T12:00:05.200Z")), at("2024-06-14T12:00:05Z"));
+        // A clock moved back a little waits for its own next boundary
+        assert_eq!(ticker.next_target(at("2024-06-14T11:59:58.500Z")), at("2024-06-14T11:59:59Z"));
+    }
+
+    #[tokio::test]
+    async fn high_speed_replay_skips_to_the_current_boundary() {
+        let base = Arc::new(FixedClock::new(at("2024-06-14T12:00:00Z")));
+        let clock = Arc::new(AcceleratedClock::new(base, at("2024-06-14T13:00:00.400Z"), 3600.0));
+        let mut ticker = Ticker::new(Duration::seconds(1), clock);
+        // An hour behind, as a sender at 3600x would be after a second of falling behind
+        ticker.last = Some(at("2024-06-14T12:00:00Z"));
+
+        let other = tokio::spawn(async {});
+        assert_eq!(ticker.tick().await, at("2024-06-14T13:00:00Z"));
+        // The tick did not sleep, and still let the other task run
+        assert!(other.is_finished());
+        assert_eq!(ticker.next_target(at("2024-06-14T13:00:00.400Z")), at("2024-06-14T13:00:01Z"));
+    }
+}
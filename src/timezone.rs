@@ -0,0 +1,128 @@
+use chrono::{ DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc };
+use chrono_tz::Tz;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Zone {
+    // Accepts an IANA name ("Asia/Tokyo"), a fixed offset ("+09:00", "UTC-5", "GMT+5:30")
+    // or "local". Returns None when the string is neither.
+    pub fn parse(value: &str) -> Option<Zone> {
+        let value = value.trim();
+        if value.is_empty() || value.eq_ignore_ascii_case("local") {
+            return Some(Zone::Local);
+        }
+        if let Some(offset) = parse_offset(value) {
+            return Some(Zone::Fixed(offset));
+        }
+        value.parse::<Tz>().ok().map(Zone::Named)
+    }
+
+    pub fn convert<T: TimeZone>(&self, dt: DateTime<T>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Local => dt.with_timezone(&Local).fixed_offset(),
+            Zone::Fixed(offset) => dt.with_timezone(offset),
+            Zone::Named(tz) => dt.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    // RFC 3339, or a date and time without offset that is read in this zone
+    pub fn parse_datetime(&self, value: &str) -> Option<DateTime<Utc>> {
+        if let Ok(dt) = DateTime::parse_from_rfc3339(value.trim()) {
+            return Some(dt.with_timezone(&Utc));
+        }
+        let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(value.trim(), format).ok())?;
+        match self {
+            Zone::Local => Local.from_local_datetime(&naive).earliest().map(|dt| dt.with_timezone(&Utc)),
+            Zone::Fixed(offset) => offset.from_local_datetime(&naive).earliest().map(|dt| dt.with_timezone(&Utc)),
+            Zone::Named(tz) => tz.from_local_datetime(&naive).earliest().map(|dt| dt.with_timezone(&Utc)),
+        }
+    }
+}
+
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let upper = value.to_ascii_uppercase();
+    let rest = upper
+        .strip_prefix("UTC")
+        .or_else(|| upper.strip_prefix("GMT"))
+        .unwrap_or(&upper);
+
+    if rest.is_empty() || rest == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+
+    let (sign, digits) = match rest.chars().next()? {
+        '+' => (1, &rest[1..]),
+        '-' => (-1, &rest[1..]),
+        _ => {
+            return None;
+        }
+    };
+
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if digits.len() > 2 => digits.split_at(digits.len() - 2),
+        None => (digits, "0"),
+    };
+    if hours.is_empty() || !hours.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    if !minutes.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{ Timelike, Utc };
+
+    #[test]
+    fn parses_names_and_offsets() {
+        assert_eq!(Zone::parse("local"), Some(Zone::Local));
+        assert_eq!(Zone::parse("Asia/Tokyo"), Some(Zone::Named(chrono_tz::Asia::Tokyo)));
+        assert_eq!(Zone::parse("+09:00"), Some(Zone::Fixed(FixedOffset::east_opt(9 * 3600).unwrap())));
+        assert_eq!(Zone::parse("UTC-5"), Some(Zone::Fixed(FixedOffset::west_opt(5 * 3600).unwrap())));
+        assert_eq!(Zone::parse("GMT+0530"), Some(Zone::Fixed(FixedOffset::east_opt(19800).unwrap())));
+        assert_eq!(Zone::parse("Mars/Olympus_Mons"), None);
+        assert_eq!(Zone::parse("+24:00"), None);
+    }
+
+    #[test]
+    fn named_zones_follow_dst() {
+        let zone = Zone::parse("America/New_York").unwrap();
+        // 2024-03-10 02:00 EST jumps to 03:00 EDT
+        let before = DateTime::parse_from_rfc3339("2024-03-10T06:59:59Z").unwrap().with_timezone(&Utc);
+        let after = DateTime::parse_from_rfc3339("2024-03-10T07:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(zone.convert(before).hour(), 1);
+        assert_eq!(zone.convert(after).hour(), 3);
+    }
+
+    #[test]
+    fn naive_datetimes_are_read_in_the_zone() {
+        let expected = DateTime::parse_from_rfc3339("2024-12-31T15:00:00Z").unwrap().with_timezone(&Utc);
+        let tokyo = Zone::parse("Asia/Tokyo").unwrap();
+        assert_eq!(tokyo.parse_datetime("2025-01-01 00:00:00"), Some(expected));
+        assert_eq!(tokyo.parse_datetime("2025-01-01T00:00"), None);
+        assert_eq!(Zone::Local.parse_datetime("2025-01-01T00:00:00+09:00"), Some(expected));
+        // A December date in New York is read with the winter offset (UTC-5)
+        let new_york = Zone::parse("America/New_York").unwrap();
+        assert_eq!(
+            new_york.parse_datetime("2024-12-31 10:00"),
+            Some(expected)
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{ Deserialize, Serialize };
+use vrchat_osc::rosc::OscType;
+
+use crate::unit::ValueKind;
+
+// A step applied to the value of an order, after the unit has made it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Transform {
+    // x * n
+    #[serde(rename = "scale")]
+    Scale(f64),
+    // x + n
+    #[serde(rename = "offset")]
+    Offset(f64),
+    // Keeps x between min and max, either of which can be omitted
+    #[serde(rename = "clamp")]
+    Clamp {
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    // 1 - x
+    #[serde(rename = "invert")]
+    Invert,
+    // Rounds x down to a multiple of 1/n. 1 stays 1, so 0 to 1 gives n + 1 values
+    #[serde(rename = "quantize")]
+    Quantize(u32),
+    // Whole part of x, modulo n (always 0 to n - 1)
+    #[serde(rename = "modulo")]
+    Modulo(i64),
+    // Sends the value as another type
+    #[serde(rename = "cast")]
+    Cast(CastType),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CastType {
+    #[serde(rename = "int")]
+    Int,
+    #[serde(rename = "float")]
+    Float,
+    #[serde(rename = "bool")]
+    Bool,
+}
+
+impl Transform {
+    // quantize and modulo need a non-zero n
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, Transform::Quantize(0) | Transform::Modulo(0))
+    }
+
+    fn apply(&self, x: f64) -> f64 {
+        match self {
+            Transform::Scale(n) => x * n,
+            Transform::Offset(n) => x + n,
+            Transform::Clamp { min, max } => x.max(min.unwrap_or(f64::MIN)).min(max.unwrap_or(f64::MAX)),
+            Transform::Invert => 1.0 - x,
+            Transform::Quantize(0) | Transform::Modulo(0) => x,
+            Transform::Quantize(n) => (x * (*n as f64)).floor() / (*n as f64),
+            Transform::Modulo(n) => x.floor().rem_euclid(*n as f64),
+            Transform::Cast(CastType::Int) => x.floor(),
+            Transform::Cast(CastType::Bool) => if x != 0.0 { 1.0 } else { 0.0 },
+            Transform::Cast(CastType::Float) => x,
+        }
+    }
+}
+
+// Runs the value through the transforms. It keeps its type unless cast, and ints are rounded down
+pub fn apply(value: OscType, transforms: &[Transform]) -> OscType {
+    if transforms.is_empty() {
+        return value;
+    }
+    let (mut x, mut cast) = match value {
+        OscType::Int(i) => (i as f64, CastType::Int),
+        OscType::Float(f) => (f as f64, CastType::Float),
+        OscType::Bool(b) => (if b { 1.0 } else { 0.0 }, CastType::Bool),
+        // Nothing to send, or not a number
+        other => return other,
+    };
+    for transform in transforms {
+        x = transform.apply(x);
+        if let Transform::Cast(to) = transform {
+            cast = *to;
+        }
+    }
+    match cast {
+        CastType::Int => OscType::Int(x.floor() as i32),
+        CastType::Float => OscType::Float(x as f32),
+        CastType::Bool => OscType::Bool(x != 0.0),
+    }
+}
+
+// What the unit sends after the transforms
+pub fn value_kind(kind: ValueKind, transforms: &[Transform]) -> ValueKind {
+    if transforms.is_empty() {
+        return kind;
+    }
+    let (mut range, mut cast) = match kind {
+        ValueKind::Int { min, max } => (min.zip(max).map(|(min, max)| (min as f64, max as f64)), CastType::Int),
+        ValueKind::Float { min, max } => (min.zip(max).map(|(min, max)| (min as f64, max as f64)), CastType::Float),
+        ValueKind::Bool => (Some((0.0, 1.0)), CastType::Bool),
+        ValueKind::None => return ValueKind::None,
+    };
+    for transform in transforms {
+        range = match transform {
+            Transform::Modulo(n) if *n != 0 => Some((0.0, (n.abs() - 1) as f64)),
+            // The others keep the order of values, so the ends stay the ends
+            _ => range.map(|(a, b)| {
+                let (a, b) = (transform.apply(a), transform.apply(b));
+                (a.min(b), a.max(b))
+            }),
+        };
+        if let Transform::Cast(to) = transform {
+            cast = *to;
+        }
+    }
+    match cast {
+        CastType::Int => ValueKind::Int {
+            min: range.map(|(min, _)| min.floor() as i32),
+            max: range.map(|(_, max)| max.floor() as i32),
+        },
+        CastType::Float => ValueKind::Float {
+            min: range.map(|(min, _)| min as f32),
+            max: range.map(|(_, max)| max as f32),
+        },
+        CastType::Bool => ValueKind::Bool,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Vec<Transform> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parses_from_json() {
+        assert_eq!(
+            parse(r#"["invert", {"scale": 2}, {"clamp": {"max": 1}}, {"quantize": 12}, {"cast": "int"}]"#),
+            vec![
+                Transform::Invert,
+                Transform::Scale(2.0),
+                Transform::Clamp { min: None, max: Some(1.0) },
+                Transform::Quantize(12),
+                Transform::Cast(CastType::Int)
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_based_hour() {
+        let transforms = parse(r#"[{"modulo": 12}]"#);
+        assert_eq!(apply(OscType::Int(12), &transforms), OscType::Int(0));
+        assert_eq!(apply(OscType::Int(11), &transforms), OscType::Int(11));
+    }
+
+    #[test]
+    fn signed_float() {
+        let transforms = parse(r#"[{"scale": 2}, {"offset": -1}]"#);
+        assert_eq!(apply(OscType::Float(0.0), &transforms), OscType::Float(-1.0));
+        assert_eq!(apply(OscType::Float(0.75), &transforms), OscType::Float(0.5));
+        assert_eq!(
+            value_kind(ValueKind::Float { min: Some(0.0), max: Some(1.0) }, &transforms),
+            ValueKind::Float { min: Some(-1.0), max: Some(1.0) }
+        );
+    }
+
+    #[test]
+    fn quantize_clamp_and_invert() {
+        assert_eq!(apply(OscType::Float(0.49), &[Transform::Quantize(4)]), OscType::Float(0.25));
+        assert_eq!(apply(OscType::Float(1.0), &[Transform::Quantize(4)]), OscType::Float(1.0));
+        assert_eq!(apply(OscType::Float(0.3), &[Transform::Invert]), OscType::Float(0.7));
+        let clamp = Transform::Clamp { min: Some(0.0), max: Some(10.0) };
+        assert_eq!(apply(OscType::Int(42), std::slice::from_ref(&clamp)), OscType::Int(10));
+        assert_eq!(apply(OscType::Int(-3), &[clamp]), OscType::Int(0));
+        // Invalid steps are skipped
+        assert_eq!(apply(OscType::Float(0.3), &[Transform::Quantize(0)]), OscType::Float(0.3));
+    }
+
+    #[test]
+    fn casts() {
+        let to_int = [Transform::Scale(60.0), Transform::Cast(CastType::Int)];
+        assert_eq!(apply(OscType::Float(0.51), &to_int), OscType::Int(30));
+        assert_eq!(apply(OscType::Int(0), &[Transform::Cast(CastType::Bool)]), OscType::Bool(false));
+        assert_eq!(apply(OscType::Bool(true), &[Transform::Invert]), OscType::Bool(false));
+        assert_eq!(apply(OscType::Bool(true), &[Transform::Cast(CastType::Float)]), OscType::Float(1.0));
+        // Ints stay ints, rounded down
+        assert_eq!(apply(OscType::Int(23), &[Transform::Scale(0.5)]), OscType::Int(11));
+        assert_eq!(apply(OscType::Nil, &to_int), OscType::Nil);
+        assert_eq!(
+            value_kind(ValueKind::Float { min: Some(0.0), max: Some(1.0) }, &to_int),
+            ValueKind::Int { min: Some(0), max: Some(60) }
+        );
+        assert_eq!(
+            value_kind(ValueKind::Int { min: Some(1), max: Some(12) }, &[Transform::Modulo(12)]),
+            ValueKind::Int { min: Some(0), max: Some(11) }
+        );
+    }
+}
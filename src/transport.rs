@@ -0,0 +1,278 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{ IpAddr, SocketAddr };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use tokio::io::AsyncWriteExt;
+use tokio::net::{ lookup_host, TcpStream, UdpSocket };
+use vrchat_osc::rosc::{ encoder, OscPacket };
+
+use crate::config::{ Destination, Protocol };
+use crate::log::{ print_flush, print_log, LogType };
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// A stuck TCP peer must not hold up the sender for long
+const TCP_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub enum SendError {
+    Resolve { host: String, error: io::Error },
+    NoAddress { host: String },
+    Bind(io::Error),
+    Connect { addr: SocketAddr, error: io::Error },
+    Encode(String),
+    Send { addr: SocketAddr, error: io::Error },
+    // Dropped without trying while waiting to retry after an earlier error
+    Backoff { remaining: Duration },
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Resolve { host, error } => write!(f, "could not resolve {}: {}", host, error),
+            SendError::NoAddress { host } => write!(f, "{} has no address", host),
+            SendError::Bind(error) => write!(f, "could not bind a socket: {}", error),
+            SendError::Connect { addr, error } => write!(f, "could not connect to {}: {}", addr, error),
+            SendError::Encode(error) => write!(f, "could not encode the packet: {}", error),
+            SendError::Send { addr, error } => write!(f, "could not send to {}: {}", addr, error),
+            SendError::Backoff { remaining } =>
+                write!(f, "waiting {:.1} s before retrying", remaining.as_secs_f64()),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+enum Connection {
+    Udp { socket: UdpSocket, addr: SocketAddr },
+    Tcp { stream: TcpStream, addr: SocketAddr },
+}
+
+// One destination. The resolved address and socket are kept until a send fails
+pub struct Target {
+    host: String,
+    port: u16,
+    protocol: Protocol,
+    connection: Option<Connection>,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Target {
+    pub fn new(host: &str, port: u16, protocol: Protocol) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            protocol,
+            connection: None,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    pub async fn send(&mut self, packet: &OscPacket) -> Result<(), SendError> {
+        if let Some(retry_at) = self.retry_at {
+            let now = Instant::now();
+            if now < retry_at {
+                return Err(SendError::Backoff { remaining: retry_at - now });
+            }
+        }
+        // Not a network problem, so no reason to back off
+        let bytes = match self.protocol {
+            Protocol::Udp => encoder::encode(packet),
+            // Size-prefixed as in OSC 1.0
+            Protocol::Tcp => encoder::encode_tcp(packet),
+        }.map_err(|error| SendError::Encode(format!("{:?}", error)))?;
+
+        let result = self.try_send(&bytes).await;
+        match result {
+            Ok(()) => {
+                self.failures = 0;
+                self.retry_at = None;
+            }
+            Err(_) => {
+                // Resolve and bind again on the next attempt
+                self.connection = None;
+                self.failures += 1;
+                self.retry_at = Some(Instant::now() + backoff(self.failures));
+            }
+        }
+        result
+    }
+
+    async fn try_send(&mut self, bytes: &[u8]) -> Result<(), SendError> {
+        if self.connection.is_none() {
+            self.connection = Some(connect(&self.host, self.port, self.protocol).await?);
+        }
+        match self.connection.as_mut().unwrap() {
+            Connection::Udp { socket, addr } =>
+                socket
+                    .send_to(bytes, *addr).await
+                    .map(|_| ())
+                    .map_err(|error| SendError::Send { addr: *addr, error }),
+            Connection::Tcp { stream, addr } =>
+                tokio::time::timeout(TCP_TIMEOUT, stream.write_all(bytes)).await
+                    .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+                    .map_err(|error| SendError::Send { addr: *addr, error }),
+        }
+    }
+}
+
+// 1, 2, 4, ... seconds, up to MAX_BACKOFF
+fn backoff(failures: u32) -> Duration {
+    INITIAL_BACKOFF.saturating_mul(1 << failures.saturating_sub(1).min(16)).min(MAX_BACKOFF)
+}
+
+async fn connect(host: &str, port: u16, protocol: Protocol) -> Result<Connection, SendError> {
+    let addr = resolve(host, port).await?;
+    match protocol {
+        Protocol::Udp => {
+            let local = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+            let socket = UdpSocket::bind(local).await.map_err(SendError::Bind)?;
+            Ok(Connection::Udp { socket, addr })
+        }
+        Protocol::Tcp => {
+            let stream = tokio::time::timeout(TCP_TIMEOUT, TcpStream::connect(addr)).await
+                .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+                .map_err(|error| SendError::Connect { addr, error })?;
+            let _ = stream.set_nodelay(true);
+            Ok(Connection::Tcp { stream, addr })
+        }
+    }
+}
+
+// IP addresses (IPv6 with or without brackets) or host names
+async fn resolve(host: &str, port: u16) -> Result<SocketAddr, SendError> {
+    if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    lookup_host((host, port)).await
+        .map_err(|error| SendError::Resolve { host: host.to_string(), error })?
+        .next()
+        .ok_or(SendError::NoAddress { host: host.to_string() })
+}
+
+// Each target has a lock of its own, so a slow destination does not hold up the others
+type Targets = HashMap<(String, u16, Protocol), Arc<tokio::sync::Mutex<Target>>>;
+
+static TARGETS: Lazy<Mutex<Targets>> = Lazy::new(||
+    Mutex::new(HashMap::new())
+);
+
+// Sends through the long-lived socket of the destination, logging failures and recovery
+pub async fn send(packet: &OscPacket, destination: &Destination) -> Result<(), SendError> {
+    let (host, port) = (destination.host.as_str(), destination.port);
+    let target = TARGETS.lock()
+        .unwrap()
+        .entry((host.to_string(), port, destination.protocol))
+        .or_insert_with(|| Arc::new(tokio::sync::Mutex::new(Target::new(host, port, destination.protocol))))
+        .clone();
+    let mut target = target.lock().await;
+    let failures = target.failures;
+
+    let result = target.send(packet).await;
+    match &result {
+        Ok(()) if failures > 0 => {
+            print_flush(
+                print_log(
+                    t!("send_recovered", address = format!("{}:{}", host, port), count = failures),
+                    LogType::INFO
+                )
+            );
+        }
+        Err(SendError::Backoff { .. }) | Ok(()) => {}
+        Err(error) => {
+            print_flush(
+                print_log(
+                    t!(
+                        "send_failed",
+                        address = format!("{}:{}", host, port),
+                        error = error,
+                        retry = backoff(target.failures).as_secs()
+                    ),
+                    LogType::WARN
+                )
+            );
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vrchat_osc::rosc::{ OscMessage, OscType };
+
+    fn packet() -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: "/avatar/parameters/osc_clock@second_i".to_string(),
+            args: vec![OscType::Int(0)],
+        })
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(3), Duration::from_secs(4));
+        assert_eq!(backoff(6), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn resolves_ip_literals() {
+        assert_eq!(resolve("127.0.0.1", 9000).await.unwrap(), "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(resolve("::1", 9000).await.unwrap(), "[::1]:9000".parse().unwrap());
+        assert_eq!(resolve("[::1]", 9000).await.unwrap(), "[::1]:9000".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn reuses_the_socket() {
+        let listener = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut target = Target::new("127.0.0.1", port, Protocol::Udp);
+        let local_addr = |target: &Target| match &target.connection {
+            Some(Connection::Udp { socket, .. }) => socket.local_addr().unwrap(),
+            _ => panic!("expected a UDP socket"),
+        };
+
+        target.send(&packet()).await.unwrap();
+        let local = local_addr(&target);
+        target.send(&packet()).await.unwrap();
+        assert_eq!(local_addr(&target), local);
+
+        let mut buf = [0; 128];
+        for _ in 0..2 {
+            let (_, from) = listener.recv_from(&mut buf).unwrap();
+            assert_eq!(from.port(), local.port());
+        }
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_a_failure() {
+        let mut target = Target::new("127.0.0.1", 9000, Protocol::Udp);
+        // Sending from an IPv4 socket to port 0 is rejected
+        target.port = 0;
+        assert!(target.send(&packet()).await.is_err());
+        assert!(target.connection.is_none());
+        assert!(matches!(target.send(&packet()).await, Err(SendError::Backoff { .. })));
+    }
+
+    #[tokio::test]
+    async fn sends_size_prefixed_packets_over_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut target = Target::new("127.0.0.1", port, Protocol::Tcp);
+        target.send(&packet()).await.unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut length = [0; 4];
+        io::Read::read_exact(&mut stream, &mut length).unwrap();
+        let mut body = vec![0; u32::from_be_bytes(length) as usize];
+        io::Read::read_exact(&mut stream, &mut body).unwrap();
+        assert_eq!(body, encoder::encode(&packet()).unwrap());
+    }
+}
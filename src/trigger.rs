@@ -0,0 +1,215 @@
+use once_cell::sync::Lazy;
+use serde::{ Deserialize, Serialize };
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+use vrchat_osc::rosc::OscType;
+
+use crate::order::Order;
+use crate::schedule::ScheduleValue;
+
+// When an update_handler fires, judged from the received value and the one received before it
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum Trigger {
+    // true, or any value that is not a bool
+    #[default]
+    #[serde(rename = "default")]
+    Default,
+    // false (or 0) to true (or non-zero)
+    #[serde(rename = "rising")]
+    Rising,
+    // true (or non-zero) to false (or 0)
+    #[serde(rename = "falling")]
+    Falling,
+    // Any value different from the previous one
+    #[serde(rename = "change")]
+    Change,
+    // Becomes the given value
+    #[serde(rename = "equals")]
+    Equals(ScheduleValue),
+    // Crosses over the threshold
+    #[serde(rename = "above")]
+    Above(f64),
+    // Crosses under the threshold
+    #[serde(rename = "below")]
+    Below(f64),
+}
+
+impl Trigger {
+    pub fn fires(&self, previous: Option<&OscType>, value: Option<&OscType>) -> bool {
+        match self {
+            Trigger::Default => !matches!(value, Some(OscType::Bool(false))),
+            Trigger::Rising => truthy(value) && !truthy(previous),
+            Trigger::Falling => !truthy(value) && truthy(previous),
+            Trigger::Change => value.is_some() && value != previous,
+            Trigger::Equals(expected) => equals(value, expected) && !equals(previous, expected),
+            Trigger::Above(threshold) => above(value, *threshold) && !above(previous, *threshold),
+            Trigger::Below(threshold) => below(value, *threshold) && !below(previous, *threshold),
+        }
+    }
+}
+
+fn number(value: Option<&OscType>) -> Option<f64> {
+    match value? {
+        OscType::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        OscType::Int(i) => Some(*i as f64),
+        OscType::Long(l) => Some(*l as f64),
+        OscType::Float(f) => Some(*f as f64),
+        OscType::Double(d) => Some(*d),
+        _ => None,
+    }
+}
+
+fn truthy(value: Option<&OscType>) -> bool {
+    number(value).is_some_and(|n| n != 0.0)
+}
+
+fn equals(value: Option<&OscType>, expected: &ScheduleValue) -> bool {
+    // 1 and 1.0 are the same number, whichever type the sender used
+    match (number(value), number(Some(&expected.to_osc()))) {
+        (Some(a), Some(b)) => a == b,
+        _ => value == Some(&expected.to_osc()),
+    }
+}
+
+fn above(value: Option<&OscType>, threshold: f64) -> bool {
+    number(value).is_some_and(|n| n > threshold)
+}
+
+fn below(value: Option<&OscType>, threshold: f64) -> bool {
+    number(value).is_some_and(|n| n < threshold)
+}
+
+// What the handlers have received so far
+pub struct HandlerStates {
+    // Last value per handler and received address, as a pattern can match many addresses
+    values: HashMap<(String, String), OscType>,
+    // Last time each handler fired, for debouncing
+    fired: HashMap<String, Instant>,
+}
+
+impl HandlerStates {
+    pub fn new() -> Self {
+        Self {
+            values: HashMap::new(),
+            fired: HashMap::new(),
+        }
+    }
+
+    // Records the value and returns whether the handler fires
+    pub fn fires(&mut self, order: &Order, address: &str, value: Option<&OscType>, now: Instant) -> bool {
+        let key = (order.address.clone(), address.to_string());
+        let previous = match value {
+            Some(value) => self.values.insert(key, value.clone()),
+            None => self.values.get(&key).cloned(),
+        };
+
+        if !order.trigger.clone().unwrap_or_default().fires(previous.as_ref(), value) {
+            return false;
+        }
+        if let (Some(debounce_ms), Some(fired)) = (order.debounce_ms, self.fired.get(&order.address)) {
+            if now.duration_since(*fired) < Duration::from_millis(debounce_ms) {
+                return false;
+            }
+        }
+        self.fired.insert(order.address.clone(), now);
+        true
+    }
+}
+
+pub static HANDLER_STATES: Lazy<Mutex<HandlerStates>> = Lazy::new(|| Mutex::new(HandlerStates::new()));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unit::UnitType;
+
+    const T: Option<&OscType> = Some(&OscType::Bool(true));
+    const F: Option<&OscType> = Some(&OscType::Bool(false));
+
+    fn handler(trigger: Option<Trigger>, debounce_ms: Option<u64>) -> Order {
+        let mut order = Order::new(UnitType::UpdateHandler, "/avatar/parameters/Grounded");
+        order.trigger = trigger;
+        order.debounce_ms = debounce_ms;
+        order
+    }
+
+    #[test]
+    fn default_trigger() {
+        assert!(Trigger::Default.fires(None, T));
+        assert!(!Trigger::Default.fires(None, F));
+        assert!(Trigger::Default.fires(None, Some(&OscType::Int(0))));
+        // Messages without arguments must not panic
+        assert!(Trigger::Default.fires(None, None));
+    }
+
+    #[test]
+    fn edges() {
+        assert!(Trigger::Rising.fires(F, T));
+        assert!(Trigger::Rising.fires(None, T));
+        assert!(!Trigger::Rising.fires(T, T));
+        assert!(!Trigger::Rising.fires(T, F));
+        assert!(Trigger::Falling.fires(T, F));
+        assert!(!Trigger::Falling.fires(None, F));
+        assert!(!Trigger::Falling.fires(F, F));
+        assert!(Trigger::Rising.fires(Some(&OscType::Int(0)), Some(&OscType::Int(2))));
+        assert!(!Trigger::Rising.fires(None, None));
+    }
+
+    #[test]
+    fn change() {
+        assert!(Trigger::Change.fires(None, Some(&OscType::Int(1))));
+        assert!(!Trigger::Change.fires(Some(&OscType::Int(1)), Some(&OscType::Int(1))));
+        assert!(Trigger::Change.fires(Some(&OscType::Int(1)), Some(&OscType::Int(2))));
+        assert!(!Trigger::Change.fires(Some(&OscType::Int(1)), None));
+    }
+
+    #[test]
+    fn equals_and_thresholds() {
+        let equals = Trigger::Equals(ScheduleValue::Int(3));
+        assert!(equals.fires(None, Some(&OscType::Int(3))));
+        assert!(equals.fires(None, Some(&OscType::Float(3.0))));
+        assert!(!equals.fires(Some(&OscType::Int(3)), Some(&OscType::Int(3))));
+        assert!(!equals.fires(None, Some(&OscType::Int(4))));
+
+        let above = Trigger::Above(0.5);
+        assert!(above.fires(Some(&OscType::Float(0.4)), Some(&OscType::Float(0.6))));
+        assert!(!above.fires(Some(&OscType::Float(0.6)), Some(&OscType::Float(0.7))));
+        assert!(!above.fires(None, Some(&OscType::Float(0.5))));
+        let below = Trigger::Below(0.5);
+        assert!(below.fires(Some(&OscType::Float(0.6)), Some(&OscType::Float(0.4))));
+        assert!(!below.fires(Some(&OscType::Float(0.4)), Some(&OscType::Float(0.3))));
+    }
+
+    #[test]
+    fn parses_from_json() {
+        let parse = |json: &str| serde_json::from_str::<Trigger>(json).unwrap();
+        assert_eq!(parse("\"rising\""), Trigger::Rising);
+        assert_eq!(parse("{\"equals\": true}"), Trigger::Equals(ScheduleValue::Bool(true)));
+        assert_eq!(parse("{\"above\": 0.5}"), Trigger::Above(0.5));
+    }
+
+    #[test]
+    fn remembers_values_per_address() {
+        let mut states = HandlerStates::new();
+        let order = handler(Some(Trigger::Rising), None);
+        let now = Instant::now();
+        assert!(states.fires(&order, "/a", T, now));
+        assert!(!states.fires(&order, "/a", T, now));
+        // Another address matched by the same handler has its own previous value
+        assert!(states.fires(&order, "/b", T, now));
+        assert!(!states.fires(&order, "/a", F, now));
+        assert!(states.fires(&order, "/a", T, now));
+    }
+
+    #[test]
+    fn debounces_bursts() {
+        let mut states = HandlerStates::new();
+        let order = handler(None, Some(500));
+        let now = Instant::now();
+        assert!(states.fires(&order, "/a", T, now));
+        assert!(!states.fires(&order, "/a", T, now + Duration::from_millis(100)));
+        assert!(!states.fires(&order, "/b", T, now + Duration::from_millis(499)));
+        assert!(states.fires(&order, "/a", T, now + Duration::from_millis(500)));
+    }
+}
@@ -1,15 +1,20 @@
-use chrono::{ Local, Timelike, Datelike };
+use chrono::{ DateTime, FixedOffset, NaiveDate, Timelike, Datelike };
 use vrchat_osc::rosc::OscType;
 use serde::{ Serialize, Deserialize };
 
-use crate::message::SyncFlag;
+use crate::order::Order;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UnitType {
+    #[serde(rename = "millisecond_int")]
+    MillisecondInt,
+
     #[serde(rename = "second_int")]
     SecondInt,
     #[serde(rename = "second_float")]
     SecondFloat,
+    #[serde(rename = "second_float_mixed")]
+    SecondFloatMixed,
 
     #[serde(rename = "minute_int")]
     MinuteInt,
@@ -56,6 +61,27 @@ pub enum UnitType {
     #[serde(rename = "month_float_mixed")]
     MonthFloatMixed,
 
+    #[serde(rename = "day_of_year_int")]
+    DayOfYearInt,
+    #[serde(rename = "day_of_year_float")]
+    DayOfYearFloat,
+    #[serde(rename = "day_of_year_float_mixed")]
+    DayOfYearFloatMixed,
+
+    #[serde(rename = "days_in_month_int")]
+    DaysInMonthInt,
+
+    #[serde(rename = "iso_week_int")]
+    IsoWeekInt,
+    #[serde(rename = "iso_week_year")]
+    IsoWeekYear,
+
+    #[serde(rename = "quarter_int")]
+    QuarterInt,
+
+    #[serde(rename = "is_leap_year")]
+    IsLeapYear,
+
     #[serde(rename = "year")]
     Year,
 
@@ -68,176 +94,309 @@ pub enum UnitType {
     #[serde(rename = "year_3")]
     Year3,
 
+    #[serde(rename = "digit")]
+    Digit,
+
+    #[serde(rename = "countdown_day_int")]
+    CountdownDayInt,
+    #[serde(rename = "countdown_hour_int")]
+    CountdownHourInt,
+    #[serde(rename = "countdown_minute_int")]
+    CountdownMinuteInt,
+    #[serde(rename = "countdown_second_int")]
+    CountdownSecondInt,
+
+    #[serde(rename = "countup_day_int")]
+    CountupDayInt,
+    #[serde(rename = "countup_hour_int")]
+    CountupHourInt,
+    #[serde(rename = "countup_minute_int")]
+    CountupMinuteInt,
+    #[serde(rename = "countup_second_int")]
+    CountupSecondInt,
+
+    #[serde(rename = "countdown_progress_float")]
+    CountdownProgressFloat,
+    #[serde(rename = "countdown_reached")]
+    CountdownReached,
+
+    #[serde(rename = "heartbeat_bool")]
+    HeartbeatBool,
+    #[serde(rename = "heartbeat_int")]
+    HeartbeatInt,
+    #[serde(rename = "online")]
+    Online,
+
     #[serde(rename = "update_handler")]
     UpdateHandler
 }
 
-pub fn handle_unit(
-    unit_type: UnitType,
-    dt: chrono::DateTime<Local>,
-    sync_flag: SyncFlag
-) -> OscType {
-    let is_minute_unit = matches!(
-        unit_type,
-        UnitType::MinuteInt | UnitType::MinuteFloat
-    );
-    let is_hour_unit = matches!(
-        unit_type,
-        UnitType::Hour24Int |
+impl UnitType {
+    pub fn needs_target(&self) -> bool {
+        matches!(
+            self,
+            UnitType::CountdownDayInt |
+                UnitType::CountdownHourInt |
+                UnitType::CountdownMinuteInt |
+                UnitType::CountdownSecondInt |
+                UnitType::CountupDayInt |
+                UnitType::CountupHourInt |
+                UnitType::CountupMinuteInt |
+                UnitType::CountupSecondInt |
+                UnitType::CountdownProgressFloat |
+                UnitType::CountdownReached
+        )
+    }
+
+    pub fn needs_start(&self) -> bool {
+        matches!(self, UnitType::CountdownProgressFloat)
+    }
+
+    pub fn needs_digit(&self) -> bool {
+        matches!(self, UnitType::Digit)
+    }
+
+    // Type and range of the values sent, as advertised over OSCQuery
+    pub fn value_kind(&self) -> ValueKind {
+        match self {
+            UnitType::MillisecondInt => ValueKind::Int { min: Some(0), max: Some(999) },
+            UnitType::SecondInt |
+            UnitType::MinuteInt |
+            UnitType::CountdownMinuteInt |
+            UnitType::CountdownSecondInt |
+            UnitType::CountupMinuteInt |
+            UnitType::CountupSecondInt => ValueKind::Int { min: Some(0), max: Some(59) },
+            UnitType::Hour24Int | UnitType::CountdownHourInt | UnitType::CountupHourInt => {
+                ValueKind::Int { min: Some(0), max: Some(23) }
+            }
+            UnitType::Hour12Int | UnitType::MonthInt => ValueKind::Int { min: Some(1), max: Some(12) },
+            UnitType::DayInt => ValueKind::Int { min: Some(1), max: Some(31) },
+            UnitType::DayOfWeekInt => ValueKind::Int { min: Some(0), max: Some(6) },
+            UnitType::DayOfYearInt => ValueKind::Int { min: Some(1), max: Some(366) },
+            UnitType::DaysInMonthInt => ValueKind::Int { min: Some(28), max: Some(31) },
+            UnitType::IsoWeekInt => ValueKind::Int { min: Some(1), max: Some(53) },
+            UnitType::QuarterInt => ValueKind::Int { min: Some(1), max: Some(4) },
+            // Blank digits can be any number
+            UnitType::Year | UnitType::IsoWeekYear | UnitType::Digit => ValueKind::Int { min: None, max: None },
+            UnitType::Year0 | UnitType::Year1 | UnitType::Year2 | UnitType::Year3 => {
+                ValueKind::Int { min: Some(0), max: Some(9) }
+            }
+            UnitType::CountdownDayInt | UnitType::CountupDayInt => ValueKind::Int { min: Some(0), max: None },
+            UnitType::SecondFloat |
+            UnitType::SecondFloatMixed |
+            UnitType::MinuteFloat |
+            UnitType::MinuteFloatMixed |
             UnitType::Hour24Float |
-            UnitType::Hour12Int |
+            UnitType::Hour24FloatMixed |
             UnitType::Hour12Float |
-            UnitType::IsPm
-    );
-    let is_day_unit = matches!(
-        unit_type,
-        UnitType::DayInt |
+            UnitType::Hour12FloatMixed |
             UnitType::DayFloat |
-            UnitType::DayOfWeekInt |
+            UnitType::DayFloatMixed |
             UnitType::DayOfWeekFloat |
-            UnitType::IsPm |
-            UnitType::MonthInt |
+            UnitType::DayOfWeekFloatMixed |
             UnitType::MonthFloat |
-            UnitType::Year |
-            UnitType::Year0 |
-            UnitType::Year1 |
-            UnitType::Year2 |
-            UnitType::Year3
-    );
-
-    if
-        ((!sync_flag.contains(SyncFlag::MINUTE)) && is_minute_unit) ||
-        ((!sync_flag.contains(SyncFlag::HOUR)) && is_hour_unit) ||
-        (!(sync_flag.contains(SyncFlag::DAY)) && is_day_unit)
-    {
-        return OscType::Nil;
-    }
-    match unit_type {
-        UnitType::SecondInt => {
-            return OscType::Int(dt.second() as i32);
-        }
-        UnitType::SecondFloat => {
-            return OscType::Float(((dt.second() as f32) / 60.0) as f32);
-        }
-        UnitType::MinuteInt => {
-            return OscType::Int(dt.minute() as i32);
-        }
-        UnitType::MinuteFloat => {
-            return OscType::Float(((dt.minute() as f32) / 60.0) as f32);
-        }
-        UnitType::MinuteFloatMixed => {
-            return OscType::Float(
-                (((dt.minute() as f32) + (dt.second() as f32) / 60.0) / 60.0) as f32
-            );
+            UnitType::MonthFloatMixed |
+            UnitType::DayOfYearFloat |
+            UnitType::DayOfYearFloatMixed |
+            UnitType::CountdownProgressFloat => ValueKind::Float { min: Some(0.0), max: Some(1.0) },
+            UnitType::HeartbeatInt => ValueKind::Int { min: Some(0), max: Some(255) },
+            UnitType::IsPm |
+            UnitType::IsLeapYear |
+            UnitType::CountdownReached |
+            UnitType::HeartbeatBool |
+            UnitType::Online => ValueKind::Bool,
+            UnitType::UpdateHandler => ValueKind::None,
         }
-        UnitType::Hour24Int => {
-            return OscType::Int(dt.hour() as i32);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    Int { min: Option<i32>, max: Option<i32> },
+    Float { min: Option<f32>, max: Option<f32> },
+    Bool,
+    // Nothing is sent
+    None,
+}
+
+// Per-order parameters used by some unit types
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitParams {
+    // Instant counted down to. RFC 3339, or a date and time read in the order's time zone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    // Instant where countdown_progress_float is 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    // How often heartbeat units change, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period_ms: Option<u64>,
+    // Field shown by the digit unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<TimeField>,
+    // Digit of the field: 0 for the ones, 1 for the tens, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub place: Option<u32>,
+    // Leading zeros are sent as 0 unless this is false, then they are blank
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zero_pad: Option<bool>,
+    // Sent for a blank digit, 10 when omitted (-1 is another common choice)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blank: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeField {
+    #[serde(rename = "year")]
+    Year,
+    #[serde(rename = "month")]
+    Month,
+    #[serde(rename = "day")]
+    Day,
+    #[serde(rename = "hour24")]
+    Hour24,
+    #[serde(rename = "hour12")]
+    Hour12,
+    #[serde(rename = "minute")]
+    Minute,
+    #[serde(rename = "second")]
+    Second,
+    #[serde(rename = "millisecond")]
+    Millisecond,
+}
+
+impl TimeField {
+    fn value(&self, dt: &DateTime<FixedOffset>) -> u32 {
+        match self {
+            TimeField::Year => dt.year().max(0) as u32,
+            TimeField::Month => dt.month(),
+            TimeField::Day => dt.day(),
+            TimeField::Hour24 => dt.hour(),
+            TimeField::Hour12 => match dt.hour() % 12 {
+                0 => 12,
+                hour => hour,
+            },
+            TimeField::Minute => dt.minute(),
+            TimeField::Second => dt.second(),
+            TimeField::Millisecond => (dt.nanosecond() / 1_000_000).min(999),
         }
-        UnitType::Hour24Float => {
-            return OscType::Float(((dt.hour() as f32) / 24.0) as f32);
+    }
+}
+
+impl UnitParams {
+    pub fn is_empty(&self) -> bool {
+        *self == UnitParams::default()
+    }
+}
+
+fn days_in_month(dt: &DateTime<FixedOffset>) -> u32 {
+    let (year, month) = if dt.month() == 12 {
+        (dt.year() + 1, 1)
+    } else {
+        (dt.year(), dt.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|first_of_next| first_of_next.pred_opt())
+        .map(|last| last.day())
+        .unwrap_or(31)
+}
+
+fn days_in_year(dt: &DateTime<FixedOffset>) -> u32 {
+    NaiveDate::from_ymd_opt(dt.year(), 12, 31)
+        .map(|last| last.ordinal())
+        .unwrap_or(365)
+}
+
+pub const DEFAULT_HEARTBEAT_PERIOD_MS: u64 = 1000;
+pub const DEFAULT_BLANK_DIGIT: i32 = 10;
+
+// Digit of the value at the place (0 for the ones). Leading zeros are blank unless zero padded
+fn digit(value: u32, place: u32, zero_pad: bool, blank: i32) -> i32 {
+    let power = 10u64.checked_pow(place).unwrap_or(u64::MAX);
+    if !zero_pad && place > 0 && (value as u64) < power {
+        return blank;
+    }
+    ((value as u64 / power) % 10) as i32
+}
+
+// Splits seconds into (days, hours, minutes, seconds)
+fn split_seconds(total: i64) -> (i32, i32, i32, i32) {
+    let total = total.max(0);
+    (
+        (total / 86400) as i32,
+        ((total % 86400) / 3600) as i32,
+        ((total % 3600) / 60) as i32,
+        (total % 60) as i32,
+    )
+}
+
+pub fn handle_unit(order: &Order, dt: DateTime<FixedOffset>) -> OscType {
+    let unit_type = order.r#type;
+    match unit_type {
+        UnitType::MillisecondInt => OscType::Int((dt.nanosecond() / 1_000_000).min(999) as i32),
+        UnitType::SecondInt => OscType::Int(dt.second() as i32),
+        UnitType::SecondFloat => OscType::Float((dt.second() as f32) / 60.0),
+        UnitType::SecondFloatMixed => {
+            let millisecond = (dt.nanosecond() / 1_000_000).min(999);
+            OscType::Float(((dt.second() as f32) + (millisecond as f32) / 1000.0) / 60.0)
         }
+        UnitType::MinuteInt => OscType::Int(dt.minute() as i32),
+        UnitType::MinuteFloat => OscType::Float((dt.minute() as f32) / 60.0),
+        UnitType::MinuteFloatMixed => OscType::Float(((dt.minute() as f32) + (dt.second() as f32) / 60.0) / 60.0),
+        UnitType::Hour24Int => OscType::Int(dt.hour() as i32),
+        UnitType::Hour24Float => OscType::Float((dt.hour() as f32) / 24.0),
         UnitType::Hour24FloatMixed => {
-            return OscType::Float(
-                (((dt.hour() as f32) +
+            OscType::Float(
+                ((dt.hour() as f32) +
                     (dt.minute() as f32) / 60.0 +
                     (dt.second() as f32) / 3600.0) /
-                    24.0) as f32
-            );
-        }
-        UnitType::Hour12Int => {
-            return OscType::Int((if dt.hour() % 12 == 0 { 12 } else { dt.hour() % 12 }) as i32);
+                    24.0
+            )
         }
+        UnitType::Hour12Int => OscType::Int((if dt.hour().is_multiple_of(12) { 12 } else { dt.hour() % 12 }) as i32),
         UnitType::Hour12Float => {
-            return OscType::Float(
-                ((if dt.hour() % 12 == 0 { 12 } else { dt.hour() % 12 }) as f32) / 12.0
-            );
+            OscType::Float(
+                ((if dt.hour().is_multiple_of(12) { 12 } else { dt.hour() % 12 }) as f32) / 12.0
+            )
         }
         UnitType::Hour12FloatMixed => {
-            return OscType::Float(
-                (((if dt.hour() % 12 == 0 { 12 } else { dt.hour() % 12 }) as f32) +
+            OscType::Float(
+                (((if dt.hour().is_multiple_of(12) { 12 } else { dt.hour() % 12 }) as f32) +
                     (dt.minute() as f32) / 60.0 +
                     (dt.second() as f32) / 3600.0) /
                     12.0
-            );
-        }
-        UnitType::IsPm => {
-            return OscType::Bool(dt.hour() >= 12);
-        }
-        UnitType::DayInt => {
-            return OscType::Int(dt.day() as i32);
+            )
         }
+        UnitType::IsPm => OscType::Bool(dt.hour() >= 12),
+        UnitType::DayInt => OscType::Int(dt.day() as i32),
         UnitType::DayFloat => {
-            let max_days = dt
-                .with_day(1)
-                .unwrap()
-                .with_month((dt.month() % 12) + 1)
-                .unwrap_or_else(||
-                    dt
-                        .with_year(dt.year() + 1)
-                        .unwrap()
-                        .with_month(1)
-                        .unwrap()
-                )
-                .signed_duration_since(dt.with_day(1).unwrap())
-                .num_days() as f32;
-            return OscType::Float((dt.day() as f32) / max_days);
+            let max_days = days_in_month(&dt) as f32;
+            OscType::Float((dt.day() as f32) / max_days)
         }
         UnitType::DayFloatMixed => {
-            let max_days = dt
-                .with_day(1)
-                .unwrap()
-                .with_month((dt.month() % 12) + 1)
-                .unwrap_or_else(||
-                    dt
-                        .with_year(dt.year() + 1)
-                        .unwrap()
-                        .with_month(1)
-                        .unwrap()
-                )
-                .signed_duration_since(dt.with_day(1).unwrap())
-                .num_days() as f32;
+            let max_days = days_in_month(&dt) as f32;
             let day_fraction =
                 (dt.day() as f32) -
                 1.0 +
                 (dt.hour() as f32) / 24.0 +
                 (dt.minute() as f32) / 1440.0 +
                 (dt.second() as f32) / 86400.0;
-            return OscType::Float(day_fraction / max_days);
-        }
-        UnitType::DayOfWeekInt => {
-            return OscType::Int(dt.weekday().num_days_from_monday() as i32);
-        }
-        UnitType::DayOfWeekFloat => {
-            return OscType::Float((dt.weekday().num_days_from_monday() as f32) / 7.0);
+            OscType::Float(day_fraction / max_days)
         }
+        UnitType::DayOfWeekInt => OscType::Int(dt.weekday().num_days_from_monday() as i32),
+        UnitType::DayOfWeekFloat => OscType::Float((dt.weekday().num_days_from_monday() as f32) / 7.0),
         UnitType::DayOfWeekFloatMixed => {
             let day_fraction =
                 (dt.weekday().num_days_from_monday() as f32) +
                 (dt.hour() as f32) / 24.0 +
                 (dt.minute() as f32) / 1440.0 +
                 (dt.second() as f32) / 86400.0;
-            return OscType::Float(day_fraction / 7.0);
-        }
-        UnitType::MonthInt => {
-            return OscType::Int(dt.month() as i32);
-        }
-        UnitType::MonthFloat => {
-            return OscType::Float((dt.month() as f32) / 12.0);
+            OscType::Float(day_fraction / 7.0)
         }
+        UnitType::MonthInt => OscType::Int(dt.month() as i32),
+        UnitType::MonthFloat => OscType::Float((dt.month() as f32) / 12.0),
         UnitType::MonthFloatMixed => {
-            let max_days = dt
-                .with_day(1)
-                .unwrap()
-                .with_month((dt.month() % 12) + 1)
-                .unwrap_or_else(||
-                    dt
-                        .with_year(dt.year() + 1)
-                        .unwrap()
-                        .with_month(1)
-                        .unwrap()
-                )
-                .signed_duration_since(dt.with_day(1).unwrap())
-                .num_days() as f32;
+            let max_days = days_in_month(&dt) as f32;
             let day_fraction =
                 (dt.day() as f32) -
                 1.0 +
@@ -245,24 +404,422 @@ pub fn handle_unit(
                 (dt.minute() as f32) / 1440.0 +
                 (dt.second() as f32) / 86400.0;
             let month_fraction = (dt.month() as f32) - 1.0 + day_fraction / max_days;
-            return OscType::Float(month_fraction / 12.0);
+            OscType::Float(month_fraction / 12.0)
         }
-        UnitType::Year => {
-            return OscType::Int(dt.year() as i32);
+        UnitType::DayOfYearInt => OscType::Int(dt.ordinal() as i32),
+        UnitType::DayOfYearFloat => OscType::Float((dt.ordinal() as f32) / (days_in_year(&dt) as f32)),
+        UnitType::DayOfYearFloatMixed => {
+            let day_fraction =
+                (dt.ordinal0() as f32) +
+                (dt.hour() as f32) / 24.0 +
+                (dt.minute() as f32) / 1440.0 +
+                (dt.second() as f32) / 86400.0;
+            OscType::Float(day_fraction / (days_in_year(&dt) as f32))
         }
+        UnitType::DaysInMonthInt => OscType::Int(days_in_month(&dt) as i32),
+        // Weeks start on Monday, and week 1 holds the first Thursday of the year
+        UnitType::IsoWeekInt => OscType::Int(dt.iso_week().week() as i32),
+        // Differs from the year around New Year, e.g. 2024-12-30 is in week 1 of 2025
+        UnitType::IsoWeekYear => OscType::Int(dt.iso_week().year()),
+        UnitType::QuarterInt => OscType::Int(dt.month0() as i32 / 3 + 1),
+        UnitType::IsLeapYear => OscType::Bool(days_in_year(&dt) == 366),
+        UnitType::Year => OscType::Int(dt.year()),
         UnitType::Year0 | UnitType::Year1 | UnitType::Year2 | UnitType::Year3 => {
-            let year_str = dt.year().to_string();
-            let index = match unit_type {
-                UnitType::Year0 => 0,
-                UnitType::Year1 => 1,
-                UnitType::Year2 => 2,
-                UnitType::Year3 => 3,
+            // year_0 is the leftmost of four digits
+            let place = match unit_type {
+                UnitType::Year0 => 3,
+                UnitType::Year1 => 2,
+                UnitType::Year2 => 1,
+                UnitType::Year3 => 0,
                 _ => unreachable!(),
             };
-            return OscType::Int(
-                year_str.chars().nth(index).unwrap_or('0').to_digit(10).unwrap_or(0) as i32
-            );
+            OscType::Int(digit(TimeField::Year.value(&dt), place, true, 0))
+        }
+        UnitType::Digit => {
+            let (Some(field), Some(place)) = (order.params.field, order.params.place) else {
+                return OscType::Nil;
+            };
+            OscType::Int(
+                digit(
+                    field.value(&dt),
+                    place,
+                    order.params.zero_pad.unwrap_or(true),
+                    order.params.blank.unwrap_or(DEFAULT_BLANK_DIGIT)
+                )
+            )
+        }
+        UnitType::CountdownDayInt |
+        UnitType::CountdownHourInt |
+        UnitType::CountdownMinuteInt |
+        UnitType::CountdownSecondInt |
+        UnitType::CountupDayInt |
+        UnitType::CountupHourInt |
+        UnitType::CountupMinuteInt |
+        UnitType::CountupSecondInt |
+        UnitType::CountdownReached => {
+            let target = match order.target() {
+                Some(target) => target,
+                None => return OscType::Nil,
+            };
+            let remaining = target.signed_duration_since(dt);
+            if unit_type == UnitType::CountdownReached {
+                return OscType::Bool(remaining.num_milliseconds() <= 0);
+            }
+            // Round up while counting down, so 0 is shown only when the target is reached
+            let remaining_seconds = (remaining.num_milliseconds() + 999).div_euclid(1000);
+            let elapsed_seconds = (-remaining.num_milliseconds()).div_euclid(1000);
+            let (days, hours, minutes, seconds) = match unit_type {
+                UnitType::CountdownDayInt |
+                UnitType::CountdownHourInt |
+                UnitType::CountdownMinuteInt |
+                UnitType::CountdownSecondInt => split_seconds(remaining_seconds),
+                _ => split_seconds(elapsed_seconds),
+            };
+            OscType::Int(match unit_type {
+                UnitType::CountdownDayInt | UnitType::CountupDayInt => days,
+                UnitType::CountdownHourInt | UnitType::CountupHourInt => hours,
+                UnitType::CountdownMinuteInt | UnitType::CountupMinuteInt => minutes,
+                _ => seconds,
+            })
+        }
+        UnitType::CountdownProgressFloat => {
+            let (start, target) = match (order.start(), order.target()) {
+                (Some(start), Some(target)) => (start, target),
+                _ => return OscType::Nil,
+            };
+            let span = target.signed_duration_since(start).num_milliseconds();
+            if span <= 0 {
+                return OscType::Float(if dt >= target { 1.0 } else { 0.0 });
+            }
+            let passed = dt.signed_duration_since(start).num_milliseconds();
+            OscType::Float(((passed as f64) / (span as f64)).clamp(0.0, 1.0) as f32)
         }
-        UnitType::UpdateHandler => return OscType::Nil,
+        UnitType::HeartbeatBool | UnitType::HeartbeatInt => {
+            let period = order.params.period_ms.filter(|period| *period > 0).unwrap_or(DEFAULT_HEARTBEAT_PERIOD_MS);
+            // Counted from the time, so it keeps changing at the same pace whatever the tick interval
+            let beats = dt.timestamp_millis().div_euclid(period as i64);
+            if unit_type == UnitType::HeartbeatBool {
+                OscType::Bool(beats.rem_euclid(2) == 1)
+            } else {
+                OscType::Int(beats.rem_euclid(256) as i32)
+            }
+        }
+        // false is sent on shutdown
+        UnitType::Online => OscType::Bool(true),
+        UnitType::UpdateHandler => OscType::Nil,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn order(unit_type: UnitType) -> Order {
+        Order::new(unit_type, "/avatar/parameters/test")
+    }
+
+    fn timer(unit_type: UnitType, start: Option<&str>, target: &str) -> Order {
+        let mut order = order(unit_type);
+        order.params.start = start.map(|s| s.to_string());
+        order.params.target = Some(target.to_string());
+        order.parse_times();
+        order
+    }
+
+    fn int(unit_type: UnitType, dt: &str) -> i32 {
+        match handle_unit(&order(unit_type), at(dt)) {
+            OscType::Int(i) => i,
+            other => panic!("{:?} returned {:?}", unit_type, other),
+        }
+    }
+
+    fn float(unit_type: UnitType, dt: &str) -> f32 {
+        match handle_unit(&order(unit_type), at(dt)) {
+            OscType::Float(f) => f,
+            other => panic!("{:?} returned {:?}", unit_type, other),
+        }
+    }
+
+    fn bool(unit_type: UnitType, dt: &str) -> bool {
+        match handle_unit(&order(unit_type), at(dt)) {
+            OscType::Bool(b) => b,
+            other => panic!("{:?} returned {:?}", unit_type, other),
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "expected {}, got {}", expected, actual);
+    }
+
+    const NEW_YEARS_EVE: &str = "2024-12-31T23:59:59+09:00";
+
+    #[test]
+    fn second_units() {
+        assert_eq!(int(UnitType::SecondInt, NEW_YEARS_EVE), 59);
+        assert_close(float(UnitType::SecondFloat, NEW_YEARS_EVE), 59.0 / 60.0);
+        assert_eq!(int(UnitType::SecondInt, "2025-01-01T00:00:00+09:00"), 0);
+        assert_close(float(UnitType::SecondFloat, "2025-01-01T00:00:00+09:00"), 0.0);
+    }
+
+    #[test]
+    fn sub_second_units() {
+        assert_eq!(int(UnitType::MillisecondInt, "2024-12-31T23:59:59.750+09:00"), 750);
+        assert_eq!(int(UnitType::MillisecondInt, NEW_YEARS_EVE), 0);
+        assert_close(float(UnitType::SecondFloatMixed, "2024-12-31T23:59:59.750+09:00"), 59.75 / 60.0);
+        assert_close(float(UnitType::SecondFloatMixed, "2024-12-31T23:59:30.500+09:00"), 30.5 / 60.0);
+    }
+
+    #[test]
+    fn minute_units() {
+        assert_eq!(int(UnitType::MinuteInt, NEW_YEARS_EVE), 59);
+        assert_close(float(UnitType::MinuteFloat, NEW_YEARS_EVE), 59.0 / 60.0);
+        assert_close(float(UnitType::MinuteFloatMixed, NEW_YEARS_EVE), (59.0 + 59.0 / 60.0) / 60.0);
+        assert_close(float(UnitType::MinuteFloatMixed, "2024-06-15T10:30:30+00:00"), 30.5 / 60.0);
+    }
+
+    #[test]
+    fn hour24_units() {
+        assert_eq!(int(UnitType::Hour24Int, NEW_YEARS_EVE), 23);
+        assert_close(float(UnitType::Hour24Float, NEW_YEARS_EVE), 23.0 / 24.0);
+        assert_close(
+            float(UnitType::Hour24FloatMixed, NEW_YEARS_EVE),
+            (23.0 + 59.0 / 60.0 + 59.0 / 3600.0) / 24.0
+        );
+        assert_eq!(int(UnitType::Hour24Int, "2024-06-15T00:00:00+00:00"), 0);
+    }
+
+    #[test]
+    fn hour12_units_around_midnight_and_noon() {
+        assert_eq!(int(UnitType::Hour12Int, "2024-06-15T00:00:00+00:00"), 12);
+        assert_eq!(int(UnitType::Hour12Int, "2024-06-15T12:00:00+00:00"), 12);
+        assert_eq!(int(UnitType::Hour12Int, "2024-06-15T13:00:00+00:00"), 1);
+        assert_eq!(int(UnitType::Hour12Int, NEW_YEARS_EVE), 11);
+        assert_close(float(UnitType::Hour12Float, NEW_YEARS_EVE), 11.0 / 12.0);
+        assert_close(float(UnitType::Hour12Float, "2024-06-15T12:00:00+00:00"), 1.0);
+        assert_close(
+            float(UnitType::Hour12FloatMixed, NEW_YEARS_EVE),
+            (11.0 + 59.0 / 60.0 + 59.0 / 3600.0) / 12.0
+        );
+    }
+
+    #[test]
+    fn is_pm() {
+        assert!(!bool(UnitType::IsPm, "2024-06-15T11:59:59+00:00"));
+        assert!(bool(UnitType::IsPm, "2024-06-15T12:00:00+00:00"));
+        assert!(bool(UnitType::IsPm, NEW_YEARS_EVE));
+    }
+
+    #[test]
+    fn day_units_at_month_ends() {
+        assert_eq!(int(UnitType::DayInt, NEW_YEARS_EVE), 31);
+        assert_close(float(UnitType::DayFloat, NEW_YEARS_EVE), 1.0);
+        let mixed = float(UnitType::DayFloatMixed, NEW_YEARS_EVE);
+        assert!(mixed > 0.9999 && mixed < 1.0, "got {}", mixed);
+
+        // Leap and non-leap February
+        assert_close(float(UnitType::DayFloat, "2024-02-29T12:00:00+00:00"), 1.0);
+        assert_close(float(UnitType::DayFloat, "2023-02-28T12:00:00+00:00"), 1.0);
+        assert_close(float(UnitType::DayFloatMixed, "2024-02-29T00:00:00+00:00"), 28.0 / 29.0);
+        assert_close(float(UnitType::DayFloatMixed, "2024-03-01T00:00:00+00:00"), 0.0);
+    }
+
+    #[test]
+    fn day_of_year_units() {
+        assert_eq!(int(UnitType::DayOfYearInt, NEW_YEARS_EVE), 366);
+        assert_close(float(UnitType::DayOfYearFloat, NEW_YEARS_EVE), 1.0);
+        let mixed = float(UnitType::DayOfYearFloatMixed, NEW_YEARS_EVE);
+        assert!(mixed > 0.9999 && mixed <= 1.0, "got {}", mixed);
+        assert_eq!(int(UnitType::DayOfYearInt, "2023-12-31T12:00:00+00:00"), 365);
+        assert_eq!(int(UnitType::DayOfYearInt, "2024-03-01T12:00:00+00:00"), 61);
+        assert_close(float(UnitType::DayOfYearFloatMixed, "2023-01-01T00:00:00+00:00"), 0.0);
+        assert_close(float(UnitType::DayOfYearFloatMixed, "2023-07-02T12:00:00+00:00"), 0.5);
+    }
+
+    #[test]
+    fn february_and_leap_years() {
+        assert_eq!(int(UnitType::DaysInMonthInt, "2024-02-10T12:00:00+00:00"), 29);
+        assert_eq!(int(UnitType::DaysInMonthInt, "2023-02-10T12:00:00+00:00"), 28);
+        assert_eq!(int(UnitType::DaysInMonthInt, "2023-04-30T12:00:00+00:00"), 30);
+        assert_eq!(int(UnitType::DaysInMonthInt, NEW_YEARS_EVE), 31);
+        assert!(bool(UnitType::IsLeapYear, "2024-02-29T12:00:00+00:00"));
+        assert!(!bool(UnitType::IsLeapYear, "2023-02-28T12:00:00+00:00"));
+        assert!(!bool(UnitType::IsLeapYear, "2100-01-01T12:00:00+00:00"));
+        assert!(bool(UnitType::IsLeapYear, "2000-01-01T12:00:00+00:00"));
+    }
+
+    #[test]
+    fn iso_weeks_around_new_year() {
+        assert_eq!(int(UnitType::IsoWeekInt, "2024-12-30T12:00:00+00:00"), 1);
+        assert_eq!(int(UnitType::IsoWeekYear, "2024-12-30T12:00:00+00:00"), 2025);
+        assert_eq!(int(UnitType::IsoWeekInt, "2021-01-03T12:00:00+00:00"), 53);
+        assert_eq!(int(UnitType::IsoWeekYear, "2021-01-03T12:00:00+00:00"), 2020);
+        assert_eq!(int(UnitType::IsoWeekInt, "2024-06-15T12:00:00+00:00"), 24);
+    }
+
+    #[test]
+    fn quarters() {
+        assert_eq!(int(UnitType::QuarterInt, "2024-03-31T12:00:00+00:00"), 1);
+        assert_eq!(int(UnitType::QuarterInt, "2024-04-01T12:00:00+00:00"), 2);
+        assert_eq!(int(UnitType::QuarterInt, NEW_YEARS_EVE), 4);
+    }
+
+    #[test]
+    fn day_of_week_units() {
+        // 2024-12-31 is a Tuesday, 2024-12-29 a Sunday
+        assert_eq!(int(UnitType::DayOfWeekInt, NEW_YEARS_EVE), 1);
+        assert_eq!(int(UnitType::DayOfWeekInt, "2024-12-29T12:00:00+00:00"), 6);
+        assert_close(float(UnitType::DayOfWeekFloat, NEW_YEARS_EVE), 1.0 / 7.0);
+        assert_close(
+            float(UnitType::DayOfWeekFloatMixed, "2024-12-29T12:00:00+00:00"),
+            6.5 / 7.0
+        );
+    }
+
+    #[test]
+    fn month_units_at_year_end() {
+        assert_eq!(int(UnitType::MonthInt, NEW_YEARS_EVE), 12);
+        assert_close(float(UnitType::MonthFloat, NEW_YEARS_EVE), 1.0);
+        // Rounds to 1.0 in f32, but must never wrap around or go negative
+        let mixed = float(UnitType::MonthFloatMixed, NEW_YEARS_EVE);
+        assert!(mixed > 0.9999 && mixed <= 1.0, "got {}", mixed);
+        assert_close(float(UnitType::MonthFloatMixed, "2025-01-01T00:00:00+00:00"), 0.0);
+        assert_close(
+            float(UnitType::MonthFloatMixed, "2024-02-15T00:00:00+00:00"),
+            (1.0 + 14.0 / 29.0) / 12.0
+        );
+    }
+
+    #[test]
+    fn year_units() {
+        assert_eq!(int(UnitType::Year, NEW_YEARS_EVE), 2024);
+        assert_eq!(int(UnitType::Year0, NEW_YEARS_EVE), 2);
+        assert_eq!(int(UnitType::Year1, NEW_YEARS_EVE), 0);
+        assert_eq!(int(UnitType::Year2, NEW_YEARS_EVE), 2);
+        assert_eq!(int(UnitType::Year3, NEW_YEARS_EVE), 4);
+    }
+
+    #[test]
+    fn value_kinds_match_the_values_sent() {
+        let dt = "2024-12-31T23:59:59.999+09:00";
+        for unit_type in [UnitType::MillisecondInt, UnitType::SecondInt, UnitType::Hour12Int, UnitType::Year3] {
+            let ValueKind::Int { min, max } = unit_type.value_kind() else {
+                panic!("{:?} is not an int", unit_type);
+            };
+            let value = int(unit_type, dt);
+            assert!(min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max));
+        }
+        assert_eq!(UnitType::SecondFloatMixed.value_kind(), ValueKind::Float { min: Some(0.0), max: Some(1.0) });
+        assert!((0.0..=1.0).contains(&float(UnitType::MonthFloatMixed, dt)));
+        assert_eq!(UnitType::IsPm.value_kind(), ValueKind::Bool);
+        assert_eq!(UnitType::UpdateHandler.value_kind(), ValueKind::None);
+    }
+
+    #[test]
+    fn digits() {
+        assert_eq!(digit(2024, 0, true, 10), 4);
+        assert_eq!(digit(2024, 3, true, 10), 2);
+        assert_eq!(digit(7, 1, true, 10), 0);
+        assert_eq!(digit(7, 1, false, 10), 10);
+        assert_eq!(digit(7, 1, false, -1), -1);
+        // The ones are never blank
+        assert_eq!(digit(0, 0, false, 10), 0);
+        assert_eq!(digit(999, 12, true, 10), 0);
+
+        let digit_of = |field: TimeField, place: u32, zero_pad: Option<bool>, dt: &str| {
+            let mut order = order(UnitType::Digit);
+            order.params.field = Some(field);
+            order.params.place = Some(place);
+            order.params.zero_pad = zero_pad;
+            handle_unit(&order, at(dt))
+        };
+        let dt = "2024-03-09T07:05:59.123+09:00";
+        assert_eq!(digit_of(TimeField::Minute, 1, None, dt), OscType::Int(0));
+        assert_eq!(digit_of(TimeField::Minute, 0, None, dt), OscType::Int(5));
+        assert_eq!(digit_of(TimeField::Hour24, 1, Some(false), dt), OscType::Int(10));
+        assert_eq!(digit_of(TimeField::Hour12, 0, None, "2024-03-09T12:00:00+09:00"), OscType::Int(2));
+        assert_eq!(digit_of(TimeField::Millisecond, 2, None, dt), OscType::Int(1));
+        assert_eq!(digit_of(TimeField::Year, 2, None, dt), OscType::Int(0));
+        // Field and place are required
+        assert_eq!(handle_unit(&order(UnitType::Digit), at(dt)), OscType::Nil);
+    }
+
+    #[test]
+    fn heartbeats() {
+        let beat = |unit_type: UnitType, period_ms: Option<u64>, dt: &str| {
+            let mut order = order(unit_type);
+            order.params.period_ms = period_ms;
+            handle_unit(&order, at(dt))
+        };
+        let a = "2024-01-01T00:00:00.000+00:00";
+        let b = "2024-01-01T00:00:01.000+00:00";
+        assert_ne!(beat(UnitType::HeartbeatBool, None, a), beat(UnitType::HeartbeatBool, None, b));
+        assert_eq!(beat(UnitType::HeartbeatBool, Some(2000), a), beat(UnitType::HeartbeatBool, Some(2000), b));
+        assert_eq!(beat(UnitType::HeartbeatBool, Some(0), a), beat(UnitType::HeartbeatBool, None, a));
+
+        let OscType::Int(count) = beat(UnitType::HeartbeatInt, Some(250), a) else { panic!() };
+        assert_eq!(beat(UnitType::HeartbeatInt, Some(250), "2024-01-01T00:00:00.250+00:00"), OscType::Int((count + 1) % 256));
+        assert!((0..256).contains(&count));
+
+        assert_eq!(beat(UnitType::Online, None, a), OscType::Bool(true));
+    }
+
+    #[test]
+    fn update_handler_is_never_sent() {
+        assert_eq!(handle_unit(&order(UnitType::UpdateHandler), at(NEW_YEARS_EVE)), OscType::Nil);
+    }
+
+    #[test]
+    fn countdown_to_new_year() {
+        let target = "2025-01-01T00:00:00+09:00";
+        let eval = |unit_type, dt| handle_unit(&timer(unit_type, None, target), at(dt));
+
+        assert_eq!(eval(UnitType::CountdownDayInt, "2024-12-30T22:58:30+09:00"), OscType::Int(1));
+        assert_eq!(eval(UnitType::CountdownHourInt, "2024-12-30T22:58:30+09:00"), OscType::Int(1));
+        assert_eq!(eval(UnitType::CountdownMinuteInt, "2024-12-30T22:58:30+09:00"), OscType::Int(1));
+        assert_eq!(eval(UnitType::CountdownSecondInt, "2024-12-30T22:58:30+09:00"), OscType::Int(30));
+        assert_eq!(eval(UnitType::CountdownSecondInt, NEW_YEARS_EVE), OscType::Int(1));
+        assert_eq!(eval(UnitType::CountdownReached, NEW_YEARS_EVE), OscType::Bool(false));
+
+        // Stops at zero once reached
+        assert_eq!(eval(UnitType::CountdownSecondInt, "2025-01-01T00:00:00+09:00"), OscType::Int(0));
+        assert_eq!(eval(UnitType::CountdownDayInt, "2025-01-02T00:00:00+09:00"), OscType::Int(0));
+        assert_eq!(eval(UnitType::CountdownReached, "2025-01-01T00:00:00+09:00"), OscType::Bool(true));
+    }
+
+    #[test]
+    fn countup_from_event_start() {
+        let target = "2025-01-01T00:00:00+09:00";
+        let eval = |unit_type, dt| handle_unit(&timer(unit_type, None, target), at(dt));
+
+        assert_eq!(eval(UnitType::CountupSecondInt, NEW_YEARS_EVE), OscType::Int(0));
+        assert_eq!(eval(UnitType::CountupDayInt, "2025-01-03T01:02:03+09:00"), OscType::Int(2));
+        assert_eq!(eval(UnitType::CountupHourInt, "2025-01-03T01:02:03+09:00"), OscType::Int(1));
+        assert_eq!(eval(UnitType::CountupMinuteInt, "2025-01-03T01:02:03+09:00"), OscType::Int(2));
+        assert_eq!(eval(UnitType::CountupSecondInt, "2025-01-03T01:02:03+09:00"), OscType::Int(3));
+    }
+
+    #[test]
+    fn countdown_progress_between_start_and_target() {
+        let order = timer(
+            UnitType::CountdownProgressFloat,
+            Some("2024-12-31T00:00:00+09:00"),
+            "2025-01-01T00:00:00+09:00"
+        );
+        let eval = |dt| handle_unit(&order, at(dt));
+
+        assert_eq!(eval("2024-12-30T00:00:00+09:00"), OscType::Float(0.0));
+        assert_eq!(eval("2024-12-31T12:00:00+09:00"), OscType::Float(0.5));
+        assert_eq!(eval("2025-01-02T00:00:00+09:00"), OscType::Float(1.0));
+    }
+
+    #[test]
+    fn timer_units_without_target_are_not_sent() {
+        assert_eq!(handle_unit(&order(UnitType::CountdownSecondInt), at(NEW_YEARS_EVE)), OscType::Nil);
+        let no_start = timer(UnitType::CountdownProgressFloat, None, "2025-01-01T00:00:00+09:00");
+        assert_eq!(handle_unit(&no_start, at(NEW_YEARS_EVE)), OscType::Nil);
     }
 }